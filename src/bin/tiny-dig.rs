@@ -0,0 +1,231 @@
+//! A small dig: `tiny-dig [@server] [name] [type] [class] [+options]`.
+
+use std::env;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::process;
+use std::time::{Duration, Instant};
+
+use tiny_dns::{
+    config::{parse_server, Config},
+    dig::{self, QueryInfo},
+    packet::{class::DnsClass, qtype::QueryType},
+    resolve::{query, trace_lookup, QueryOptions},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+const USAGE: &str = "\
+Usage: tiny-dig [@server] [-p port] [name] [type] [class] [+option...]
+
+Options:
+  +tcp / +notcp         query over TCP instead of UDP
+  +rec / +norec         set the RD (recursion desired) flag, on by default
+  +cd / +nocd           set the CD (checking disabled) flag
+  +dnssec / +nodnssec   set the DO (DNSSEC OK) bit
+  +bufsize=N            advertise an EDNS UDP payload size of N
+  +edns / +noedns       send an OPT record, on by default
+  +timeout=N            wait N seconds for the answer
+  +short                print only the rdata of the answers
+  +json                 print the whole answer as JSON
+  +trace                resolve from the root servers down, showing each step
+
+Without @server the first nameserver of /etc/resolv.conf is asked.";
+
+enum Output {
+    Full,
+    Short,
+    Json,
+}
+
+struct Request {
+    server: Option<String>,
+    port: u16,
+    name: Option<String>,
+    qtype: Option<QueryType>,
+    options: QueryOptions,
+    output: Output,
+    trace: bool,
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!(";; {:#}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let request = parse_args(env::args().skip(1))?;
+
+    // like dig, ask for the root servers when no name is given
+    let (name, qtype) = match request.name {
+        Some(name) => (name, request.qtype.unwrap_or(QueryType::A)),
+        None => (".".to_string(), request.qtype.unwrap_or(QueryType::NS)),
+    };
+
+    if request.trace {
+        let mut config = Config::default();
+        config.timeouts.upstream = request.options.timeout;
+        let trace = trace_lookup(&config, &name, qtype);
+        print!("{}", dig::format_trace(&trace));
+        if trace.result.is_err() {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let server = match &request.server {
+        Some(server) => parse_server(server, request.port)?,
+        None => SocketAddr::new(system_nameserver()?, request.port),
+    };
+
+    let started = Instant::now();
+    let res = query(&name, qtype, server, &request.options)?;
+    let info = QueryInfo {
+        server,
+        tcp: request.options.tcp,
+        elapsed: started.elapsed(),
+    };
+
+    match request.output {
+        Output::Full => print!("{}", dig::format_response(&res, Some(&info))),
+        Output::Short => print!("{}", dig::format_short(&res)),
+        Output::Json => println!("{:#}", dig::to_json(&res, Some(&info))),
+    }
+    Ok(())
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Request> {
+    let mut request = Request {
+        server: None,
+        port: 53,
+        name: None,
+        qtype: None,
+        options: QueryOptions::default(),
+        output: Output::Full,
+        trace: false,
+    };
+    let mut class = None;
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            println!("{}", USAGE);
+            process::exit(0);
+        } else if arg == "-p" {
+            let port = args.next().ok_or_else(|| anyhow!("-p needs a port"))?;
+            request.port = port
+                .parse()
+                .map_err(|_| anyhow!("invalid port {:?}", port))?;
+        } else if let Some(server) = arg.strip_prefix('@') {
+            request.server = Some(server.to_string());
+        } else if let Some(option) = arg.strip_prefix('+') {
+            parse_option(&mut request, option)?;
+        } else if let (None, Ok(qtype)) = (request.qtype, arg.parse::<QueryType>()) {
+            // type and class keywords count as such wherever they stand, as
+            // with dig; the word left over is the name
+            request.qtype = Some(qtype);
+        } else if let (None, Ok(parsed)) = (class, arg.parse::<DnsClass>()) {
+            class = Some(parsed);
+        } else if request.name.is_none() {
+            request.name = Some(arg);
+        } else {
+            bail!("unexpected argument {:?}\n\n{}", arg, USAGE);
+        }
+    }
+    if let Some(class) = class {
+        request.options.class = class;
+    }
+
+    Ok(request)
+}
+
+fn parse_option(request: &mut Request, option: &str) -> Result<()> {
+    let (key, value) = match option.split_once('=') {
+        Some((key, value)) => (key, Some(value)),
+        None => (option, None),
+    };
+    let (enabled, key) = match key.strip_prefix("no") {
+        Some(key) => (false, key),
+        None => (true, key),
+    };
+    let number = |value: Option<&str>| -> Result<u64> {
+        value
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| anyhow!("+{} needs a number", key))
+    };
+
+    let options = &mut request.options;
+    match key {
+        "tcp" | "vc" => options.tcp = enabled,
+        "rec" | "recurse" => options.recursion_desired = enabled,
+        "cd" | "cdflag" => options.checking_disabled = enabled,
+        "dnssec" => options.dnssec_ok = enabled,
+        "edns" => {
+            options.edns_size = match enabled {
+                true => options.edns_size.or(QueryOptions::default().edns_size),
+                false => None,
+            }
+        }
+        "bufsize" => {
+            let size = number(value)?;
+            options.edns_size =
+                Some(u16::try_from(size).map_err(|_| anyhow!("+bufsize too large"))?);
+        }
+        "trace" => request.trace = enabled,
+        "timeout" => options.timeout = Duration::from_secs(number(value)?.max(1)),
+        "short" if enabled => request.output = Output::Short,
+        "json" if enabled => request.output = Output::Json,
+        "short" | "json" => request.output = Output::Full,
+        _ => bail!("unknown option +{}\n\n{}", option, USAGE),
+    }
+    Ok(())
+}
+
+/// The first nameserver listed in /etc/resolv.conf.
+fn system_nameserver() -> Result<IpAddr> {
+    let conf = fs::read_to_string("/etc/resolv.conf").unwrap_or_default();
+    conf.lines()
+        .filter_map(|line| line.strip_prefix("nameserver"))
+        .filter_map(|rest| rest.trim().parse().ok())
+        .next()
+        .ok_or_else(|| anyhow!("no nameserver in /etc/resolv.conf, give one as @server"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Request {
+        parse_args(args.split_whitespace().map(String::from)).unwrap()
+    }
+
+    #[test]
+    fn takes_class_and_type_before_the_name() {
+        let request = parse("@127.0.0.1 ch txt version.bind");
+        assert_eq!(request.server.as_deref(), Some("127.0.0.1"));
+        assert_eq!(request.name.as_deref(), Some("version.bind"));
+        assert_eq!(request.qtype, Some(QueryType::TXT));
+        assert_eq!(request.options.class, DnsClass::CH);
+    }
+
+    #[test]
+    fn takes_name_type_and_class_in_any_order() {
+        for args in [
+            "example.com aaaa in",
+            "in example.com aaaa",
+            "aaaa in example.com",
+        ] {
+            let request = parse(args);
+            assert_eq!(request.name.as_deref(), Some("example.com"), "{}", args);
+            assert_eq!(request.qtype, Some(QueryType::AAAA), "{}", args);
+            assert_eq!(request.options.class, DnsClass::IN, "{}", args);
+        }
+    }
+
+    #[test]
+    fn rejects_a_second_name() {
+        let args = ["example.com", "example.org"].map(String::from);
+        assert!(parse_args(args.into_iter()).is_err());
+    }
+}
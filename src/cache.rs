@@ -0,0 +1,261 @@
+//! A cache of answers from upstream servers.
+//!
+//! Answers tailored to the client's network through EDNS Client Subnet
+//! (RFC 7871) are only reused for clients within the scope the upstream
+//! server gave for them.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::packet::{
+    edns::{ClientSubnet, Edns, EdnsOption},
+    name,
+    qtype::QueryType,
+    record::DnsRecord,
+    rscode::ResultCode,
+    DnsPacket,
+};
+
+/// Upper bound on how long we keep anything, whatever its TTL.
+const MAX_TTL: u32 = 86400;
+
+struct Entry {
+    /// The network the answer is valid for, `None` if it's valid for all.
+    scope: Option<(IpAddr, u8)>,
+    rescode: ResultCode,
+    answers: Vec<DnsRecord>,
+    authorities: Vec<DnsRecord>,
+    resources: Vec<DnsRecord>,
+    inserted: Instant,
+    expires: Instant,
+}
+
+impl Entry {
+    fn covers(&self, client: Option<IpAddr>) -> bool {
+        match (self.scope, client) {
+            (None, _) => true,
+            (Some((network, prefix)), Some(client)) => {
+                network.is_ipv4() == client.is_ipv4()
+                    && ClientSubnet::mask(client, prefix) == network
+            }
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// The cached answers by question, and how many there are in all.
+#[derive(Default)]
+struct Entries {
+    by_question: HashMap<(String, QueryType), Vec<Entry>>,
+    len: usize,
+}
+
+pub struct Cache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl Cache {
+    /// A cache holding at most `capacity` answers.
+    pub fn new(capacity: usize) -> Cache {
+        Cache {
+            capacity,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Look up a cached answer for a client in the network `client`, with
+    /// the TTLs counted down by the time spent in the cache.
+    ///
+    /// A scoped answer comes back with a Client Subnet option holding its
+    /// scope, as if it had just arrived from upstream.
+    pub fn get(&self, qname: &str, qtype: QueryType, client: Option<IpAddr>) -> Option<DnsPacket> {
+        let entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+
+        let entry = entries
+            .by_question
+            .get(&(name::normalize(qname), qtype))?
+            .iter()
+            .filter(|e| e.expires > now && e.covers(client))
+            .max_by_key(|e| e.scope.map(|(_, prefix)| prefix))?;
+
+        let elapsed = now.duration_since(entry.inserted).as_secs() as u32;
+        let age = |records: &[DnsRecord]| {
+            records
+                .iter()
+                .cloned()
+                .map(|mut rec| {
+                    rec.set_ttl(rec.ttl().saturating_sub(elapsed));
+                    rec
+                })
+                .collect()
+        };
+
+        let mut packet = DnsPacket::new();
+        packet.header.rescode = entry.rescode;
+        packet.answers = age(&entry.answers);
+        packet.authorities = age(&entry.authorities);
+        packet.resources = age(&entry.resources);
+        if let Some((network, prefix)) = entry.scope {
+            let mut edns = Edns::default();
+            edns.options.push(EdnsOption::ClientSubnet(ClientSubnet {
+                source_prefix: prefix,
+                scope_prefix: prefix,
+                address: network,
+            }));
+            packet.edns = Some(edns);
+        }
+
+        Some(packet)
+    }
+
+    /// Remember an upstream answer to a query sent on behalf of `subnet`.
+    pub fn insert(
+        &self,
+        qname: &str,
+        qtype: QueryType,
+        subnet: Option<&ClientSubnet>,
+        packet: &DnsPacket,
+    ) {
+        let ttl = match Self::ttl_of(packet) {
+            Some(ttl) if ttl > 0 => ttl.min(MAX_TTL),
+            _ => return,
+        };
+
+        // An answer can't be more specific than the network we asked for.
+        let scope = match (subnet, packet.edns.as_ref().and_then(|e| e.client_subnet())) {
+            (Some(asked), Some(answered)) if answered.scope_prefix > 0 => {
+                let prefix = answered.scope_prefix.min(asked.source_prefix);
+                Some((ClientSubnet::mask(asked.address, prefix), prefix))
+            }
+            _ => None,
+        };
+
+        let now = Instant::now();
+        let entry = Entry {
+            scope,
+            rescode: packet.header.rescode,
+            answers: packet.answers.clone(),
+            authorities: packet.authorities.clone(),
+            resources: packet.resources.clone(),
+            inserted: now,
+            expires: now + Duration::from_secs(ttl as u64),
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len >= self.capacity {
+            entries.evict(now);
+        }
+
+        let slot = entries
+            .by_question
+            .entry((name::normalize(qname), qtype))
+            .or_default();
+        let before = slot.len();
+        slot.retain(|e| e.scope != scope);
+        slot.push(entry);
+        let after = slot.len();
+        entries.len = entries.len + after - before;
+    }
+
+    /// How long an answer may be cached: the smallest TTL in it, or for
+    /// negative answers the SOA's negative caching TTL (RFC 2308).
+    fn ttl_of(packet: &DnsPacket) -> Option<u32> {
+        match packet.header.rescode {
+            ResultCode::NOERROR | ResultCode::NXDOMAIN => {}
+            _ => return None,
+        }
+
+        if packet.answers.is_empty() {
+            return packet.authorities.iter().find_map(|rec| match rec {
+                DnsRecord::SOA { ttl, minimum, .. } => Some((*ttl).min(*minimum)),
+                _ => None,
+            });
+        }
+
+        packet
+            .answers
+            .iter()
+            .chain(&packet.authorities)
+            .chain(&packet.resources)
+            .map(DnsRecord::ttl)
+            .min()
+    }
+}
+
+impl Entries {
+    /// Make room for one more entry: drop everything expired, and if that
+    /// isn't enough, the entry closest to expiry.
+    fn evict(&mut self, now: Instant) {
+        let mut expired = 0;
+        for slot in self.by_question.values_mut() {
+            let before = slot.len();
+            slot.retain(|e| e.expires > now);
+            expired += before - slot.len();
+        }
+        self.by_question.retain(|_, slot| !slot.is_empty());
+        self.len -= expired;
+        if expired > 0 {
+            return;
+        }
+
+        let oldest = self
+            .by_question
+            .iter()
+            .flat_map(|(key, slot)| slot.iter().enumerate().map(move |(i, e)| (key, i, e)))
+            .min_by_key(|(_, _, e)| e.expires)
+            .map(|(key, i, _)| (key.clone(), i));
+        if let Some((key, i)) = oldest {
+            if let Some(slot) = self.by_question.get_mut(&key) {
+                slot.remove(i);
+                self.len -= 1;
+                if slot.is_empty() {
+                    self.by_question.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::packet::class::DnsClass;
+
+    fn answer(qname: &str, ttl: u32) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.answers.push(DnsRecord::A {
+            domain: qname.to_string(),
+            class: DnsClass::IN,
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl,
+        });
+        packet
+    }
+
+    #[test]
+    fn evicts_the_entry_closest_to_expiry_when_full() {
+        let cache = Cache::new(2);
+        cache.insert("a.example.", QueryType::A, None, &answer("a.example.", 100));
+        cache.insert("b.example.", QueryType::A, None, &answer("b.example.", 10));
+        cache.insert("c.example.", QueryType::A, None, &answer("c.example.", 100));
+
+        assert_eq!(cache.entries.lock().unwrap().len, 2);
+        assert!(cache.get("a.example.", QueryType::A, None).is_some());
+        assert!(cache.get("b.example.", QueryType::A, None).is_none());
+        assert!(cache.get("c.example.", QueryType::A, None).is_some());
+    }
+
+    #[test]
+    fn replacing_an_answer_keeps_the_count() {
+        let cache = Cache::new(10);
+        cache.insert("a.example.", QueryType::A, None, &answer("a.example.", 100));
+        cache.insert("a.example.", QueryType::A, None, &answer("a.example.", 50));
+        assert_eq!(cache.entries.lock().unwrap().len, 1);
+    }
+}
@@ -0,0 +1,96 @@
+//! Answers to CHAOS-class queries, which describe the server itself rather
+//! than anything in the DNS tree.
+
+use crate::config::{Disclosure, IdentityConfig};
+use crate::packet::{
+    class::DnsClass, name, qtype::QueryType, question::DnsQuestion, record::DnsRecord,
+    rscode::ResultCode,
+};
+
+const IDENTITY_NAMES: [&str; 2] = ["id.server", "hostname.bind"];
+const VERSION_NAMES: [&str; 2] = ["version.server", "version.bind"];
+
+/// Answer a CHAOS-class question from the identity configuration.
+///
+/// Names we don't know are refused, and those the configuration hides get
+/// no data or are refused, as it says.
+pub fn answer(question: &DnsQuestion, identity: &IdentityConfig) -> (ResultCode, Vec<DnsRecord>) {
+    let is_one_of = |names: &[&str]| names.iter().any(|n| name::eq(n, &question.name));
+
+    let (text, disclosure) = if is_one_of(&IDENTITY_NAMES) {
+        (&identity.identity, identity.disclose_identity)
+    } else if is_one_of(&VERSION_NAMES) {
+        (&identity.version, identity.disclose_version)
+    } else {
+        return (ResultCode::REFUSED, Vec::new());
+    };
+
+    match (disclosure, question.qtype) {
+        (Disclosure::Refuse, _) => (ResultCode::REFUSED, Vec::new()),
+        (Disclosure::Answer, QueryType::TXT) => {
+            // a <character-string> holds at most 255 bytes
+            let mut data: Vec<Vec<u8>> = text.as_bytes().chunks(255).map(<[u8]>::to_vec).collect();
+            if data.is_empty() {
+                data.push(Vec::new());
+            }
+            let record = DnsRecord::TXT {
+                domain: question.name.clone(),
+                class: DnsClass::CH,
+                data,
+                ttl: 0,
+            };
+            (ResultCode::NOERROR, vec![record])
+        }
+        _ => (ResultCode::NOERROR, Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question(name: &str) -> DnsQuestion {
+        DnsQuestion {
+            name: name.to_string(),
+            qtype: QueryType::TXT,
+            class: DnsClass::CH,
+        }
+    }
+
+    #[test]
+    fn splits_long_text_into_character_strings() {
+        let identity = IdentityConfig {
+            version: "v".repeat(300),
+            ..IdentityConfig::default()
+        };
+        let (rescode, answers) = answer(&question("version.bind."), &identity);
+        assert_eq!(rescode, ResultCode::NOERROR);
+        match &answers[..] {
+            [DnsRecord::TXT { data, .. }] => {
+                assert_eq!(data.iter().map(Vec::len).collect::<Vec<_>>(), [255, 45])
+            }
+            _ => panic!("expected a TXT record, got {:?}", answers),
+        }
+    }
+
+    #[test]
+    fn hides_or_refuses_as_configured() {
+        let identity = IdentityConfig {
+            disclose_identity: Disclosure::Hide,
+            disclose_version: Disclosure::Refuse,
+            ..IdentityConfig::default()
+        };
+        assert_eq!(
+            answer(&question("id.server."), &identity),
+            (ResultCode::NOERROR, Vec::new())
+        );
+        assert_eq!(
+            answer(&question("version.server."), &identity),
+            (ResultCode::REFUSED, Vec::new())
+        );
+        assert_eq!(
+            answer(&question("authors.bind."), &IdentityConfig::default()),
+            (ResultCode::REFUSED, Vec::new())
+        );
+    }
+}
@@ -0,0 +1,571 @@
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::LevelFilter;
+use rand::random;
+use serde::{Deserialize, Deserializer};
+
+use crate::packet::edns::{ClientSubnet, MIN_UDP_PAYLOAD_SIZE};
+use crate::packet::encoding::{from_base64, from_hex};
+use crate::packet::name;
+use crate::packet::tsig;
+
+/// Settings of the DNS server.
+///
+/// Everything can be set from a TOML file, see [`Config::load`]; settings
+/// missing from the file keep their defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Addresses to answer queries on over both UDP and TCP, port 53 unless
+    /// given.
+    #[serde(deserialize_with = "server_addrs")]
+    pub listen: Vec<SocketAddr>,
+    pub mode: Mode,
+    /// Servers that forward mode passes queries on to, port 53 unless given.
+    #[serde(deserialize_with = "server_addrs")]
+    pub upstreams: Vec<SocketAddr>,
+    /// Where recursive mode starts resolving, port 53 unless given.
+    #[serde(deserialize_with = "server_addrs")]
+    pub root_servers: Vec<SocketAddr>,
+    /// Number of answers kept in the cache.
+    pub cache_size: usize,
+    pub timeouts: TimeoutConfig,
+    pub acl: AclConfig,
+    pub logging: LoggingConfig,
+    pub identity: IdentityConfig,
+    pub cookies: CookieConfig,
+    pub client_subnet: ClientSubnetConfig,
+    /// Keys for signing messages with TSIG (RFC 8945).
+    pub tsig_keys: Vec<TsigKeyConfig>,
+    /// Zones served from master files, only in authoritative mode.
+    pub zones: Vec<ZoneConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: vec![SocketAddr::from(([0, 0, 0, 0], 2053))],
+            mode: Mode::default(),
+            upstreams: Vec::new(),
+            // f.root-servers.net
+            root_servers: vec![SocketAddr::from(([192, 5, 5, 241], 53))],
+            cache_size: 10_000,
+            timeouts: TimeoutConfig::default(),
+            acl: AclConfig::default(),
+            logging: LoggingConfig::default(),
+            identity: IdentityConfig::default(),
+            cookies: CookieConfig::default(),
+            client_subnet: ClientSubnetConfig::default(),
+            tsig_keys: Vec::new(),
+            zones: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Read a TOML configuration file and check it makes sense. Relative
+    /// zone file paths are taken relative to the configuration file.
+    pub fn load(path: &Path) -> Result<Config> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+        let mut config: Config =
+            toml::from_str(&text).with_context(|| format!("invalid {}", path.display()))?;
+
+        if let Some(dir) = path.parent() {
+            for zone in &mut config.zones {
+                zone.file = dir.join(&zone.file);
+            }
+        }
+
+        config
+            .validate()
+            .with_context(|| format!("invalid {}", path.display()))?;
+        Ok(config)
+    }
+
+    /// Check the settings for mistakes that would only show once the server
+    /// is running.
+    pub fn validate(&self) -> Result<()> {
+        if self.listen.is_empty() {
+            bail!("listen: at least one address is needed");
+        }
+        for (i, addr) in self.listen.iter().enumerate() {
+            if self.listen[..i].contains(addr) {
+                bail!("listen: {} is given twice", addr);
+            }
+        }
+
+        match self.mode {
+            Mode::Recursive if self.root_servers.is_empty() => {
+                bail!("root_servers: recursive mode needs at least one root server")
+            }
+            Mode::Forward if self.upstreams.is_empty() => {
+                bail!("upstreams: forward mode needs at least one upstream server")
+            }
+            Mode::Authoritative if self.zones.is_empty() => {
+                bail!("zones: authoritative mode needs at least one zone")
+            }
+            _ => {}
+        }
+        if self.mode != Mode::Authoritative && !self.zones.is_empty() {
+            bail!("zones: only served in authoritative mode");
+        }
+
+        if self.timeouts.upstream.is_zero() {
+            bail!("timeouts.upstream_ms: must be more than 0");
+        }
+        if self.timeouts.tcp_idle.is_zero() {
+            bail!("timeouts.tcp_idle_ms: must be more than 0");
+        }
+
+        if self.cookies.secret.len() < 16 {
+            bail!("cookies.secret: must be at least 16 bytes (32 hex digits)");
+        }
+        if let Some(limit) = self.cookies.cookieless_udp_limit {
+            if limit < MIN_UDP_PAYLOAD_SIZE {
+                bail!(
+                    "cookies.cookieless_udp_limit: must be at least {}",
+                    MIN_UDP_PAYLOAD_SIZE
+                );
+            }
+        }
+
+        if self.client_subnet.ipv4_prefix > 32 {
+            bail!("client_subnet.ipv4_prefix: must be at most 32");
+        }
+        if self.client_subnet.ipv6_prefix > 128 {
+            bail!("client_subnet.ipv6_prefix: must be at most 128");
+        }
+
+        for (i, key) in self.tsig_keys.iter().enumerate() {
+            if let Err(e) = name::from_text(&key.name, "") {
+                bail!("tsig_keys: {:#}", e);
+            }
+            if self.tsig_keys[..i]
+                .iter()
+                .any(|k| name::eq(&k.name, &key.name))
+            {
+                bail!("tsig_keys: {} is given twice", key.name);
+            }
+            if !name::eq(&key.algorithm, tsig::HMAC_SHA256) {
+                bail!(
+                    "tsig_keys: algorithm {} of {} is not supported, only hmac-sha256",
+                    key.algorithm,
+                    key.name
+                );
+            }
+            if key.secret.is_empty() {
+                bail!("tsig_keys: the secret of {} is empty", key.name);
+            }
+        }
+
+        for (i, zone) in self.zones.iter().enumerate() {
+            if let Err(e) = name::from_text(&zone.name, "") {
+                bail!("zones: {:#}", e);
+            }
+            if self.zones[..i]
+                .iter()
+                .any(|z| name::eq(&z.name, &zone.name))
+            {
+                bail!("zones: {} is given twice", zone.name);
+            }
+            // a secondary creates its file on the first transfer
+            if !zone.is_secondary() && !zone.file.is_file() {
+                bail!(
+                    "zones: file {} of {} does not exist",
+                    zone.file.display(),
+                    zone.name
+                );
+            }
+            if let Some(key) = zone
+                .transfer_keys
+                .iter()
+                .find(|key| self.tsig_key(key).is_none())
+            {
+                bail!(
+                    "zones: transfer key {} of {} is not in tsig_keys",
+                    key,
+                    zone.name
+                );
+            }
+            if let Some(key) = zone
+                .update_keys
+                .iter()
+                .find(|key| self.tsig_key(key).is_none())
+            {
+                bail!(
+                    "zones: update key {} of {} is not in tsig_keys",
+                    key,
+                    zone.name
+                );
+            }
+            // changes go to the primary, whose version we copy
+            if zone.is_secondary() && !zone.update_keys.is_empty() {
+                bail!("zones: update keys of secondary zone {}", zone.name);
+            }
+            if let Some(key) = &zone.primary_key {
+                if self.tsig_key(key).is_none() {
+                    bail!(
+                        "zones: primary key {} of {} is not in tsig_keys",
+                        key,
+                        zone.name
+                    );
+                }
+                if !zone.is_secondary() {
+                    bail!("zones: primary key of {} without primaries", zone.name);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The TSIG key called `name`.
+    pub fn tsig_key(&self, name: &str) -> Option<&TsigKeyConfig> {
+        self.tsig_keys.iter().find(|key| name::eq(&key.name, name))
+    }
+
+    /// The settings of the zone with apex `origin`.
+    pub fn zone(&self, origin: &str) -> Option<&ZoneConfig> {
+        self.zones.iter().find(|zone| name::eq(&zone.name, origin))
+    }
+}
+
+/// How the server finds answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Resolve names from the root down.
+    #[default]
+    Recursive,
+    /// Pass queries on to the upstream servers.
+    Forward,
+    /// Answer only from our own zones.
+    Authoritative,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    /// How long we wait for an upstream server before giving up on it.
+    #[serde(rename = "upstream_ms", deserialize_with = "millis")]
+    pub upstream: Duration,
+    /// How long a client's TCP connection may sit idle before we close it.
+    #[serde(rename = "tcp_idle_ms", deserialize_with = "millis")]
+    pub tcp_idle: Duration,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            upstream: Duration::from_secs(5),
+            tcp_idle: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Which clients may query the server. Those denied get REFUSED.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AclConfig {
+    /// Networks allowed to query; everyone if empty.
+    pub allow: Vec<Network>,
+    /// Networks refused even if `allow` lets them in.
+    pub deny: Vec<Network>,
+}
+
+impl AclConfig {
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|n| n.contains(ip)))
+            && !self.deny.iter().any(|n| n.contains(ip))
+    }
+}
+
+/// An IP network such as `192.0.2.0/24`. A bare address is a network of
+/// just that host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Network {
+    pub address: IpAddr,
+    pub prefix: u8,
+}
+
+impl Network {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        ip.is_ipv4() == self.address.is_ipv4()
+            && ClientSubnet::mask(ip, self.prefix) == self.address
+    }
+}
+
+impl FromStr for Network {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Network> {
+        let (address, prefix) = s.split_once('/').unwrap_or((s, ""));
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| anyhow!("invalid network {:?}", s))?;
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            "" => max_prefix,
+            prefix => prefix
+                .parse()
+                .ok()
+                .filter(|p| *p <= max_prefix)
+                .ok_or_else(|| anyhow!("invalid prefix length in {:?}", s))?,
+        };
+
+        Ok(Network {
+            address: ClientSubnet::mask(address, prefix),
+            prefix,
+        })
+    }
+}
+
+impl TryFrom<String> for Network {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Network> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Most verbose messages written to stderr, one of `off`, `error`,
+    /// `warn`, `info`, `debug` and `trace`. `RUST_LOG` overrides it.
+    pub level: LevelFilter,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: LevelFilter::Info,
+        }
+    }
+}
+
+/// What the server tells about itself in answer to CHAOS-class TXT queries
+/// such as `version.bind` and `id.server`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentityConfig {
+    /// Answer for `id.server` and `hostname.bind`, the host name by default.
+    pub identity: String,
+    /// Answer for `version.bind` and `version.server`.
+    pub version: String,
+    /// How queries for `id.server` and `hostname.bind` are met.
+    pub disclose_identity: Disclosure,
+    /// How queries for `version.bind` and `version.server` are met.
+    pub disclose_version: Disclosure,
+}
+
+impl Default for IdentityConfig {
+    fn default() -> Self {
+        IdentityConfig {
+            identity: hostname(),
+            version: format!("tiny-dns {}", env!("CARGO_PKG_VERSION")),
+            disclose_identity: Disclosure::default(),
+            disclose_version: Disclosure::default(),
+        }
+    }
+}
+
+/// How a CHAOS-class query about the server is met.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Disclosure {
+    /// Answer with the configured text.
+    #[default]
+    Answer,
+    /// Answer with no data, as if there were nothing to tell.
+    Hide,
+    /// Refuse the query.
+    Refuse,
+}
+
+/// How the server handles DNS Cookies (RFC 7873).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CookieConfig {
+    pub enabled: bool,
+    /// Key for server cookies, in hex. Instances behind the same anycast
+    /// address should share it; by default each process picks its own.
+    #[serde(deserialize_with = "hex_bytes")]
+    pub secret: Vec<u8>,
+    /// UDP answers larger than this are only sent to clients presenting a
+    /// valid server cookie. Others get them truncated to this size, so they
+    /// retry over TCP and can't be used for amplification.
+    pub cookieless_udp_limit: Option<usize>,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        CookieConfig {
+            enabled: true,
+            secret: random::<[u8; 16]>().to_vec(),
+            cookieless_udp_limit: None,
+        }
+    }
+}
+
+/// Whether upstream queries carry the client's network in an EDNS Client
+/// Subnet option (RFC 7871), and how much of it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientSubnetConfig {
+    pub enabled: bool,
+    /// Longest prefix of a client's IPv4 address we reveal upstream.
+    pub ipv4_prefix: u8,
+    /// Longest prefix of a client's IPv6 address we reveal upstream.
+    pub ipv6_prefix: u8,
+    /// Nameservers recursive mode reveals it to, by address. Forward mode
+    /// reveals it to every upstream; recursive mode to no others, so the
+    /// root and TLD servers never see it (RFC 7871, section 11.1).
+    pub servers: Vec<IpAddr>,
+}
+
+impl Default for ClientSubnetConfig {
+    fn default() -> Self {
+        // the prefixes recommended by RFC 7871, section 11.1
+        ClientSubnetConfig {
+            enabled: false,
+            ipv4_prefix: 24,
+            ipv6_prefix: 56,
+            servers: Vec::new(),
+        }
+    }
+}
+
+/// A zone we are authoritative for.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZoneConfig {
+    pub name: String,
+    /// Master file holding the zone's records.
+    pub file: PathBuf,
+    /// Servers to transfer the zone from, port 53 unless given. With any,
+    /// we are a secondary for the zone and save it to `file` after every
+    /// transfer.
+    #[serde(default, deserialize_with = "server_addrs")]
+    pub primaries: Vec<SocketAddr>,
+    /// Key to sign the requests to the primaries with. NOTIFY messages
+    /// signed with it are accepted from any address.
+    #[serde(default)]
+    pub primary_key: Option<String>,
+    /// Secondaries to send NOTIFY to when the zone changes, port 53 unless
+    /// given.
+    #[serde(default, deserialize_with = "server_addrs")]
+    pub notify: Vec<SocketAddr>,
+    /// Networks that may transfer the whole zone with AXFR.
+    #[serde(default)]
+    pub allow_transfer: Vec<Network>,
+    /// Keys that may transfer the zone, from whatever address.
+    #[serde(default)]
+    pub transfer_keys: Vec<String>,
+    /// Keys that may change the zone with UPDATE, from whatever address.
+    #[serde(default)]
+    pub update_keys: Vec<String>,
+}
+
+impl ZoneConfig {
+    pub fn is_secondary(&self) -> bool {
+        !self.primaries.is_empty()
+    }
+
+    /// Whether a client at `ip` may transfer the zone, having signed its
+    /// request with the key `key_name` if any.
+    pub fn allows_transfer(&self, ip: IpAddr, key_name: Option<&str>) -> bool {
+        self.allow_transfer.iter().any(|n| n.contains(ip))
+            || key_name
+                .is_some_and(|key_name| self.transfer_keys.iter().any(|k| name::eq(k, key_name)))
+    }
+
+    /// Whether an UPDATE signed with the key `key_name` may change the zone.
+    pub fn allows_update(&self, key_name: Option<&str>) -> bool {
+        key_name.is_some_and(|key_name| self.update_keys.iter().any(|k| name::eq(k, key_name)))
+    }
+
+    /// Whether a NOTIFY from `ip`, signed with the key `key_name` if any,
+    /// comes from one of our primaries.
+    pub fn allows_notify(&self, ip: IpAddr, key_name: Option<&str>) -> bool {
+        self.primaries.iter().any(|p| p.ip() == ip)
+            || key_name.is_some_and(|key_name| {
+                self.primary_key
+                    .as_deref()
+                    .is_some_and(|k| name::eq(k, key_name))
+            })
+    }
+}
+
+/// A secret shared with another server to sign the messages between us.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TsigKeyConfig {
+    pub name: String,
+    /// Only `hmac-sha256` is supported.
+    #[serde(default = "default_tsig_algorithm")]
+    pub algorithm: String,
+    /// The secret, in base64.
+    #[serde(deserialize_with = "base64_bytes")]
+    pub secret: Vec<u8>,
+}
+
+fn default_tsig_algorithm() -> String {
+    tsig::HMAC_SHA256.to_string()
+}
+
+/// Parse a server address, with or without a port: `192.0.2.1`,
+/// `192.0.2.1:5353`, `2001:db8::1` or `[2001:db8::1]:5353`.
+pub fn parse_server(s: &str, default_port: u16) -> Result<SocketAddr> {
+    if let Ok(addr) = s.parse() {
+        return Ok(addr);
+    }
+    let ip: IpAddr = s
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .map_err(|_| anyhow!("invalid server address {:?}", s))?;
+    Ok(SocketAddr::new(ip, default_port))
+}
+
+fn server_addrs<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<SocketAddr>, D::Error> {
+    Vec::<String>::deserialize(d)?
+        .iter()
+        .map(|s| parse_server(s, 53).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn millis<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+    u64::deserialize(d).map(Duration::from_millis)
+}
+
+fn hex_bytes<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+    from_hex(&String::deserialize(d)?).map_err(serde::de::Error::custom)
+}
+
+fn base64_bytes<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+    from_base64(&String::deserialize(d)?).map_err(serde::de::Error::custom)
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "tiny-dns".to_string())
+}
@@ -0,0 +1,119 @@
+//! DNS Cookies (RFC 7873), a lightweight defence against off-path spoofing.
+//!
+//! As a client we derive a cookie per upstream server from a secret picked
+//! at startup and remember the server cookies handed back to us. As a server
+//! we issue cookies laid out as in RFC 9018, authenticated with HMAC-SHA256.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use rand::random;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SERVER_COOKIE_VERSION: u8 = 1;
+
+/// How long a server cookie we issued stays valid, and how far from the
+/// future we accept one to cope with clock skew between anycast instances.
+const COOKIE_LIFETIME: u32 = 3600;
+const COOKIE_CLOCK_SKEW: u32 = 300;
+
+static CLIENT_SECRET: LazyLock<[u8; 16]> = LazyLock::new(random);
+static SERVER_COOKIES: LazyLock<Mutex<HashMap<IpAddr, Vec<u8>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn ip_octets(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+/// The client cookie we send to `server`. It is stable for the lifetime of
+/// the process, but differs per server so servers can't track us.
+pub fn client_cookie(server: IpAddr) -> [u8; 8] {
+    let mut mac = HmacSha256::new_from_slice(&*CLIENT_SECRET).expect("HMAC takes any key size");
+    mac.update(&ip_octets(server));
+
+    let mut cookie = [0; 8];
+    cookie.copy_from_slice(&mac.finalize().into_bytes()[..8]);
+    cookie
+}
+
+/// The last server cookie `server` gave us, empty if we don't have one.
+pub fn server_cookie(server: IpAddr) -> Vec<u8> {
+    SERVER_COOKIES
+        .lock()
+        .unwrap()
+        .get(&server)
+        .cloned()
+        .unwrap_or_default()
+}
+
+pub fn remember_server_cookie(server: IpAddr, cookie: &[u8]) {
+    SERVER_COOKIES
+        .lock()
+        .unwrap()
+        .insert(server, cookie.to_vec());
+}
+
+fn server_cookie_mac(
+    secret: &[u8],
+    client_cookie: &[u8; 8],
+    header: &[u8],
+    client_ip: IpAddr,
+) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC takes any key size");
+    mac.update(client_cookie);
+    mac.update(header);
+    mac.update(&ip_octets(client_ip));
+    mac
+}
+
+/// Issue a server cookie for a client: version, three reserved bytes, a
+/// timestamp and a hash binding it all to the client (RFC 9018, section 4).
+pub fn make_server_cookie(secret: &[u8], client_cookie: &[u8; 8], client_ip: IpAddr) -> Vec<u8> {
+    let mut cookie = vec![SERVER_COOKIE_VERSION, 0, 0, 0];
+    cookie.extend_from_slice(&now().to_be_bytes());
+
+    let mac = server_cookie_mac(secret, client_cookie, &cookie, client_ip);
+    cookie.extend_from_slice(&mac.finalize().into_bytes()[..8]);
+    cookie
+}
+
+/// Whether `server_cookie` is one we issued to this client and it hasn't
+/// expired.
+pub fn check_server_cookie(
+    secret: &[u8],
+    client_cookie: &[u8; 8],
+    server_cookie: &[u8],
+    client_ip: IpAddr,
+) -> bool {
+    if server_cookie.len() != 16 || server_cookie[0] != SERVER_COOKIE_VERSION {
+        return false;
+    }
+
+    let (header, hash) = server_cookie.split_at(8);
+    let timestamp = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    let now = now();
+    if timestamp > now.saturating_add(COOKIE_CLOCK_SKEW)
+        || timestamp.saturating_add(COOKIE_LIFETIME) < now
+    {
+        return false;
+    }
+
+    server_cookie_mac(secret, client_cookie, header, client_ip)
+        .verify_truncated_left(hash)
+        .is_ok()
+}
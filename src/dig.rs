@@ -0,0 +1,270 @@
+//! Printing answers the way `dig` does, shared by `tiny-dig` and the
+//! `query` subcommand.
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::packet::{
+    edns::{ede, Edns, EdnsOption},
+    encoding::to_hex,
+    name,
+    record::DnsRecord,
+    DnsPacket,
+};
+use crate::resolve::Trace;
+
+/// Where an answer came from and how long it took, for the footer.
+pub struct QueryInfo {
+    pub server: SocketAddr,
+    pub tcp: bool,
+    pub elapsed: Duration,
+}
+
+/// The full presentation: header, OPT pseudosection and all sections.
+pub fn format_response(res: &DnsPacket, info: Option<&QueryInfo>) -> String {
+    let mut out = String::new();
+    let header = &res.header;
+
+    let _ = writeln!(
+        out,
+        ";; ->>HEADER<<- opcode: {:?}, status: {}, id: {}",
+        header.opcode, header.rescode, header.id
+    );
+    let _ = writeln!(
+        out,
+        ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+        flags(res).join(" "),
+        res.questions.len(),
+        res.answers.len(),
+        res.authorities.len(),
+        res.resources.len() + res.edns.is_some() as usize
+    );
+
+    if let Some(edns) = &res.edns {
+        out.push_str("\n;; OPT PSEUDOSECTION:\n");
+        format_edns(&mut out, edns);
+    }
+
+    out.push_str("\n;; QUESTION SECTION:\n");
+    for question in &res.questions {
+        let _ = writeln!(
+            out,
+            ";{}\t\t\t{}\t{}",
+            name::or_root(&question.name),
+            question.class,
+            question.qtype
+        );
+    }
+
+    let sections = [
+        ("ANSWER", &res.answers),
+        ("AUTHORITY", &res.authorities),
+        ("ADDITIONAL", &res.resources),
+    ];
+    for (title, records) in sections {
+        if records.is_empty() {
+            continue;
+        }
+        let _ = writeln!(out, "\n;; {} SECTION:", title);
+        for rec in records {
+            let _ = writeln!(out, "{}", format_record(rec));
+        }
+    }
+
+    if let Some(info) = info {
+        let _ = writeln!(out, "\n;; Query time: {} msec", info.elapsed.as_millis());
+        let _ = writeln!(
+            out,
+            ";; SERVER: {}#{}({}) ({})",
+            info.server.ip(),
+            info.server.port(),
+            info.server.ip(),
+            if info.tcp { "TCP" } else { "UDP" }
+        );
+    }
+
+    out
+}
+
+/// What `dig +trace` prints: the referrals and answers of every server
+/// asked on the way down, then the final answer.
+pub fn format_trace(trace: &Trace) -> String {
+    let mut out = String::new();
+
+    for hop in &trace.hops {
+        let from = format!(
+            "{}#{} for {} {} in {} ms",
+            hop.server.ip(),
+            hop.server.port(),
+            hop.qname,
+            hop.qtype,
+            hop.latency.as_millis()
+        );
+        match &hop.response {
+            Ok(res) => {
+                let records: Vec<_> = if hop.referral.is_empty() {
+                    res.answers.iter().chain(&res.authorities).collect()
+                } else {
+                    hop.referral.iter().chain(&hop.glue).collect()
+                };
+                for rec in records {
+                    let _ = writeln!(out, "{}", format_record(rec));
+                }
+                let _ = writeln!(out, ";; Received {} from {}\n", res.header.rescode, from);
+            }
+            Err(e) => {
+                let _ = writeln!(out, ";; No answer from {}: {}\n", from, e);
+            }
+        }
+    }
+
+    match &trace.result {
+        Ok(res) => out.push_str(&format_response(res, None)),
+        Err(e) => {
+            let _ = writeln!(out, ";; resolution failed: {:#}", e);
+        }
+    }
+
+    out
+}
+
+/// A record as dig lines it up, with tabs between the fields.
+pub fn format_record(rec: &DnsRecord) -> String {
+    format!(
+        "{}\t\t{}\t{}\t{}\t{}",
+        name::or_root(rec.domain()),
+        rec.ttl(),
+        rec.class(),
+        rec.qtype(),
+        rec.rdata()
+    )
+}
+
+/// What `dig +short` prints: the rdata of the answers, one per line.
+pub fn format_short(res: &DnsPacket) -> String {
+    let mut out = String::new();
+    for rec in &res.answers {
+        let _ = writeln!(out, "{}", rec.rdata());
+    }
+    out
+}
+
+/// The answer as a JSON document, for scripts.
+pub fn to_json(res: &DnsPacket, info: Option<&QueryInfo>) -> Value {
+    let records = |records: &[DnsRecord]| -> Vec<Value> {
+        records
+            .iter()
+            .map(|rec| {
+                json!({
+                    "name": rec.domain(),
+                    "ttl": rec.ttl(),
+                    "class": rec.class().to_string(),
+                    "type": rec.qtype().to_string(),
+                    "data": rec.rdata().to_string(),
+                })
+            })
+            .collect()
+    };
+
+    let mut doc = json!({
+        "id": res.header.id,
+        "opcode": format!("{:?}", res.header.opcode),
+        "status": res.header.rescode.to_string(),
+        "flags": flags(res),
+        "question": res.questions.iter().map(|q| json!({
+            "name": q.name,
+            "class": q.class.to_string(),
+            "type": q.qtype.to_string(),
+        })).collect::<Vec<_>>(),
+        "answer": records(&res.answers),
+        "authority": records(&res.authorities),
+        "additional": records(&res.resources),
+    });
+
+    if let Some(edns) = &res.edns {
+        let options: Vec<_> = edns
+            .options
+            .iter()
+            .map(|option| {
+                let (name, value) = format_option(option);
+                json!({ "name": name, "value": value })
+            })
+            .collect();
+        doc["edns"] = json!({
+            "version": edns.version,
+            "udp_payload_size": edns.udp_payload_size,
+            "dnssec_ok": edns.dnssec_ok,
+            "options": options,
+        });
+    }
+
+    if let Some(info) = info {
+        doc["server"] = json!(info.server.to_string());
+        doc["transport"] = json!(if info.tcp { "tcp" } else { "udp" });
+        doc["query_time_ms"] = json!(info.elapsed.as_millis() as u64);
+    }
+
+    doc
+}
+
+fn flags(res: &DnsPacket) -> Vec<&'static str> {
+    let header = &res.header;
+    [
+        (header.response, "qr"),
+        (header.authoritative_answer, "aa"),
+        (header.truncated_message, "tc"),
+        (header.recursion_desired, "rd"),
+        (header.recursion_available, "ra"),
+        (header.authed_data, "ad"),
+        (header.checking_disabled, "cd"),
+    ]
+    .into_iter()
+    .filter_map(|(set, name)| set.then_some(name))
+    .collect()
+}
+
+fn format_edns(out: &mut String, edns: &Edns) {
+    let _ = writeln!(
+        out,
+        "; EDNS: version: {}, flags:{}; udp: {}",
+        edns.version,
+        if edns.dnssec_ok { " do" } else { "" },
+        edns.udp_payload_size
+    );
+    for option in &edns.options {
+        let (name, value) = format_option(option);
+        let _ = writeln!(out, "; {}: {}", name, value);
+    }
+}
+
+fn format_option(option: &EdnsOption) -> (String, String) {
+    match option {
+        EdnsOption::ClientSubnet(subnet) => (
+            "CLIENT-SUBNET".to_string(),
+            format!(
+                "{}/{}/{}",
+                subnet.address, subnet.source_prefix, subnet.scope_prefix
+            ),
+        ),
+        EdnsOption::Cookie { client, server } => (
+            "COOKIE".to_string(),
+            format!("{}{}", to_hex(client), to_hex(server)),
+        ),
+        EdnsOption::ExtendedError {
+            info_code,
+            extra_text,
+        } => (
+            "EDE".to_string(),
+            format!(
+                "{} ({}): ({})",
+                info_code,
+                ede::name(*info_code),
+                extra_text
+            ),
+        ),
+        EdnsOption::Unknown(code, data) => (format!("OPT{}", code), to_hex(data)),
+    }
+}
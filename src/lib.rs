@@ -1,2 +1,8 @@
+pub mod cache;
+pub mod chaos;
+pub mod config;
+pub mod cookie;
+pub mod dig;
 pub mod packet;
 pub mod resolve;
+pub mod zone;
@@ -1,16 +1,239 @@
-use std::net::UdpSocket;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use tiny_dns::resolve::query_handler;
+use tiny_dns::{
+    cache::Cache,
+    config::{parse_server, Config, Mode},
+    dig::{self, QueryInfo},
+    packet::qtype::QueryType,
+    resolve::{lookup, query_handler, recursive_lookup, tcp_query_handler, trace_lookup},
+    zone::{primary, secondary, Catalog, Zone},
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+use log::{debug, info, warn, LevelFilter};
+
+/// A small DNS server: recursive, forwarding or authoritative.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// What to do; `serve` with the default settings if left out.
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Answer DNS queries until stopped.
+    Serve(ServeArgs),
+    /// Look up a name and print the answer, like dig.
+    Query(QueryArgs),
+    /// Check a zone master file for errors.
+    CheckZone {
+        /// Name of the zone, such as `example.com.`.
+        zone: String,
+        /// The master file.
+        file: PathBuf,
+    },
+    /// Check a configuration file for errors.
+    CheckConfig {
+        /// The TOML configuration file.
+        file: PathBuf,
+    },
+}
+
+#[derive(Args, Default)]
+struct ServeArgs {
+    /// TOML configuration file; built-in defaults if not given.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Address to listen on instead of the configured ones. Can be repeated.
+    #[arg(short, long, value_parser = |s: &str| parse_server(s, 53))]
+    listen: Vec<SocketAddr>,
+    /// Port to listen on, replacing the port of every listen address.
+    #[arg(short, long)]
+    port: Option<u16>,
+    /// Forward queries to this server instead of resolving them. Can be
+    /// repeated.
+    #[arg(short, long, value_parser = |s: &str| parse_server(s, 53))]
+    forward: Vec<SocketAddr>,
+}
+
+#[derive(Args)]
+struct QueryArgs {
+    /// Name to look up.
+    name: String,
+    /// Record type to ask for.
+    #[arg(default_value = "A")]
+    qtype: QueryType,
+    /// Server to ask; resolve from the root servers down if not given.
+    #[arg(short, long, value_parser = |s: &str| parse_server(s, 53))]
+    server: Option<SocketAddr>,
+    /// Milliseconds to wait for an answer.
+    #[arg(short, long, default_value_t = 5000)]
+    timeout: u64,
+    /// Show every server asked on the way down from the root.
+    #[arg(long, conflicts_with = "server")]
+    trace: bool,
+}
 
 fn main() -> Result<()> {
-    let socket = UdpSocket::bind(("0.0.0.0", 2053))?;
+    let cli = Cli::parse();
+
+    match cli.command.unwrap_or(Command::Serve(ServeArgs::default())) {
+        Command::Serve(args) => serve(args),
+        Command::Query(args) => query(args),
+        Command::CheckZone { zone, file } => {
+            init_logging(LevelFilter::Warn);
+            let zone = Zone::load(&zone, &file)?;
+            println!(
+                "{}: ok, zone {} with serial {} and {} records",
+                file.display(),
+                zone.origin,
+                zone.serial(),
+                zone.records().count()
+            );
+            Ok(())
+        }
+        Command::CheckConfig { file } => {
+            init_logging(LevelFilter::Warn);
+            let config = Config::load(&file)?;
+            println!(
+                "{}: ok, {:?} mode on {} address(es)",
+                file.display(),
+                config.mode,
+                config.listen.len()
+            );
+            Ok(())
+        }
+    }
+}
 
-    loop {
-        match query_handler(&socket) {
-            Ok(_) => println!("== Handled successfully! ==\n\n"),
-            Err(e) => eprintln!("== An error occured: {} ==\n\n", e),
+fn init_logging(level: LevelFilter) {
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .init();
+}
+
+/// Answer queries on every configured address, over UDP and TCP, until the
+/// process is stopped.
+fn serve(args: ServeArgs) -> Result<()> {
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    if !args.listen.is_empty() {
+        config.listen = args.listen;
+    }
+    if let Some(port) = args.port {
+        for addr in &mut config.listen {
+            addr.set_port(port);
         }
     }
+    if !args.forward.is_empty() {
+        config.mode = Mode::Forward;
+        config.upstreams = args.forward;
+    }
+    config.validate()?;
+
+    init_logging(config.logging.level);
+
+    let zones = &match config.mode {
+        Mode::Authoritative => Catalog::load(&config.zones)?,
+        _ => Catalog::default(),
+    };
+    let config = &config;
+    let cache = &Cache::new(config.cache_size);
+
+    let mut sockets = Vec::new();
+    let mut listeners = Vec::new();
+    for addr in &config.listen {
+        let socket =
+            UdpSocket::bind(addr).with_context(|| format!("cannot listen on udp {}", addr))?;
+        let listener =
+            TcpListener::bind(addr).with_context(|| format!("cannot listen on tcp {}", addr))?;
+        info!("listening on {}", addr);
+        sockets.push(socket);
+        listeners.push(listener);
+    }
+
+    thread::scope(|s| {
+        if config.mode == Mode::Authoritative {
+            for zone in &config.zones {
+                match zone.is_secondary() {
+                    true => s.spawn(move || secondary::maintain(zones, config, zone)),
+                    false => s.spawn(move || primary::watch(zones, config, zone)),
+                };
+            }
+        }
+
+        for socket in &sockets {
+            s.spawn(move || loop {
+                match query_handler(socket, config, cache, zones) {
+                    Ok(_) => debug!("== Handled successfully! =="),
+                    Err(e) => warn!("== An error occured: {} ==", e),
+                }
+            });
+        }
+
+        for listener in &listeners {
+            s.spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            warn!("cannot accept connection: {}", e);
+                            continue;
+                        }
+                    };
+                    s.spawn(move || {
+                        if let Err(e) = tcp_query_handler(stream, config, cache, zones) {
+                            warn!("== An error occured: {} ==", e);
+                        }
+                    });
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn query(args: QueryArgs) -> Result<()> {
+    init_logging(LevelFilter::Warn);
+
+    let timeout = Duration::from_millis(args.timeout);
+    match args.server {
+        Some(server) => {
+            let started = Instant::now();
+            let res = lookup(&args.name, args.qtype, server, None, timeout)?;
+            let info = QueryInfo {
+                server,
+                tcp: false,
+                elapsed: started.elapsed(),
+            };
+            print!("{}", dig::format_response(&res, Some(&info)));
+        }
+        None => {
+            let mut config = Config::default();
+            config.timeouts.upstream = timeout;
+            if args.trace {
+                let trace = trace_lookup(&config, &args.name, args.qtype);
+                print!("{}", dig::format_trace(&trace));
+                if trace.result.is_err() {
+                    process::exit(1);
+                }
+            } else {
+                let res = recursive_lookup(&config, &args.name, args.qtype, None)?;
+                print!("{}", dig::format_response(&res, None));
+            }
+        }
+    }
+
+    Ok(())
 }
@@ -1,29 +1,42 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, UdpSocket};
+
 use anyhow::{anyhow, bail, Result};
 
-const BUFFER_SIZE: usize = 1024;
+use super::name;
+
+// The largest message DNS can carry, so a full response can always be
+// written before it is cut down to what the client accepts.
+const BUFFER_SIZE: usize = 65535;
 
+/// A message on the heap, holding what was received or growing as it is
+/// written, up to the largest message DNS can carry.
+#[derive(Default)]
 pub struct BytePacketBuffer {
-    pub buf: [u8; BUFFER_SIZE],
+    pub buf: Vec<u8>,
     pos: usize,
 }
 
-impl Default for BytePacketBuffer {
-    fn default() -> Self {
-        BytePacketBuffer {
-            buf: [0; BUFFER_SIZE],
-            pos: 0,
-        }
-    }
-}
-
 impl BytePacketBuffer {
     /// This gives us a fresh buffer for holding the packet contents, and a
     /// field for keeping track of where we are.
     pub fn new() -> Self {
-        BytePacketBuffer {
-            buf: [0; BUFFER_SIZE],
-            pos: 0,
-        }
+        BytePacketBuffer::default()
+    }
+
+    /// Receive one datagram from `socket`, the buffer holding just what
+    /// arrived.
+    pub fn recv_from(socket: &UdpSocket) -> io::Result<(BytePacketBuffer, SocketAddr)> {
+        let mut buf = vec![0; BUFFER_SIZE];
+        let (len, from) = socket.recv_from(&mut buf)?;
+        buf.truncate(len);
+        Ok((BytePacketBuffer { buf, pos: 0 }, from))
+    }
+
+    /// Start over, keeping the room already taken for the next message.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.pos = 0;
     }
 
     /// Current position within buffer
@@ -38,17 +51,17 @@ impl BytePacketBuffer {
     }
 
     /// Change the buffer position
-    fn seek(&mut self, pos: usize) -> Result<()> {
+    pub(crate) fn seek(&mut self, pos: usize) -> Result<()> {
         self.pos = pos;
         Ok(())
     }
 
     /// Read a single byte and move the position one step forward
     pub fn read(&mut self) -> Result<u8> {
-        if self.pos >= BUFFER_SIZE {
+        if self.pos >= self.buf.len() {
             return Err(anyhow!(
                 "buffer's size is {}, but read to {}",
-                BUFFER_SIZE,
+                self.buf.len(),
                 self.pos
             ));
         }
@@ -60,10 +73,10 @@ impl BytePacketBuffer {
 
     /// Get a signle byte, without changing the buffer position
     pub fn get(&mut self, pos: usize) -> Result<u8> {
-        if pos >= BUFFER_SIZE {
+        if pos >= self.buf.len() {
             return Err(anyhow!(
                 "buffer's size is {}, but read to {}",
-                BUFFER_SIZE,
+                self.buf.len(),
                 pos
             ));
         }
@@ -72,16 +85,30 @@ impl BytePacketBuffer {
 
     /// Get a range of bytes
     pub fn get_range(&mut self, start: usize, len: usize) -> Result<&[u8]> {
-        if start + len >= BUFFER_SIZE {
+        if start + len > self.buf.len() {
             return Err(anyhow!(
                 "buffer's size is {}, but read to {}",
-                BUFFER_SIZE,
+                self.buf.len(),
                 start + len
             ));
         }
         Ok(&self.buf[start..start + len])
     }
 
+    /// Read `len` bytes into an owned vector, stepping `len` steps forward
+    pub fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        let start = self.pos;
+        let res = self.get_range(start, len)?.to_vec();
+        self.pos += len;
+        Ok(res)
+    }
+
+    /// Read a <character-string>: a length byte followed by that many bytes
+    pub fn read_character_string(&mut self) -> Result<Vec<u8>> {
+        let len = self.read()? as usize;
+        self.read_bytes(len)
+    }
+
     /// Read two bytes, steping two steps forward
     pub fn read_u16(&mut self) -> Result<u16> {
         let byte_high = self.read()? as u16;
@@ -167,8 +194,7 @@ impl BytePacketBuffer {
                 }
 
                 let str_buffer = self.get_range(pos, len as usize)?;
-                let tag = String::from_utf8_lossy(str_buffer).to_lowercase();
-                outstr.push_str(tag.as_str());
+                outstr.push_str(&name::escape_label(str_buffer));
                 // This may produce a "redundant" dot after the domain name queried
                 // In fact, this is the formal Fully Qualified domain name.
                 outstr.push_str(delim);
@@ -185,8 +211,7 @@ impl BytePacketBuffer {
         Ok(outstr)
     }
 
-    /// Writes
-
+    /// Writes a single byte, stepping one step forward
     fn write(&mut self, val: u8) -> Result<()> {
         if self.pos >= BUFFER_SIZE {
             bail!(
@@ -195,7 +220,12 @@ impl BytePacketBuffer {
                 self.pos
             );
         }
-        self.buf[self.pos] = val;
+        if self.pos < self.buf.len() {
+            self.buf[self.pos] = val;
+        } else {
+            self.buf.resize(self.pos, 0);
+            self.buf.push(val);
+        }
         self.pos += 1;
         Ok(())
     }
@@ -222,16 +252,32 @@ impl BytePacketBuffer {
         Ok(())
     }
 
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        if self.pos == self.buf.len() && self.pos + bytes.len() <= BUFFER_SIZE {
+            self.buf.extend_from_slice(bytes);
+            self.pos += bytes.len();
+            return Ok(());
+        }
+        for b in bytes {
+            self.write(*b)?;
+        }
+        Ok(())
+    }
+
+    /// Write a <character-string>, which holds at most 255 bytes
+    pub fn write_character_string(&mut self, bytes: &[u8]) -> Result<()> {
+        if bytes.len() > 0xff {
+            bail!("character-string exceeds 255 bytes of length");
+        }
+        self.write_u8(bytes.len() as u8)?;
+        self.write_bytes(bytes)
+    }
+
     pub fn write_qname(&mut self, qname: &str) -> Result<()> {
-        for label in qname.split('.').filter(|s| s.len() > 0) {
-            let len = label.len();
-            if len > 0x3f {
-                bail!("label {} exceeds 63 characters of length", label);
-            }
-            self.write_u8(len as u8)?;
-            for b in label.as_bytes() {
-                self.write(*b)?;
-            }
+        for label in name::labels(qname) {
+            let label = name::unescape_label(label)?;
+            self.write_u8(label.len() as u8)?;
+            self.write_bytes(&label)?;
         }
 
         self.write_u8(0)?;
@@ -239,10 +285,12 @@ impl BytePacketBuffer {
         Ok(())
     }
 
-    /// Setting value in place.
-
+    /// Setting value in place, without moving the buffer position
     pub fn set(&mut self, pos: usize, val: u8) -> Result<()> {
-        self.buf[pos] = val;
+        match self.buf.get_mut(pos) {
+            Some(byte) => *byte = val,
+            None => bail!("buffer's size is {}, but set {}", self.buf.len(), pos),
+        }
         Ok(())
     }
 
@@ -253,4 +301,58 @@ impl BytePacketBuffer {
         self.set(pos, (val >> 8) as u8)?;
         self.set(pos + 1, (val & 0xff) as u8)
     }
+
+    /// Read one message from a TCP stream, on which each message is preceded
+    /// by its length in two octets. `None` means the peer closed the stream.
+    pub fn read_tcp<R: Read>(stream: &mut R) -> io::Result<Option<BytePacketBuffer>> {
+        let mut len = [0; 2];
+        match stream.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut buf)?;
+        Ok(Some(BytePacketBuffer { buf, pos: 0 }))
+    }
+
+    /// Send what was written so far as one message on a TCP stream.
+    pub fn write_tcp<W: Write>(&self, stream: &mut W) -> io::Result<()> {
+        let mut message = Vec::with_capacity(self.pos + 2);
+        message.extend_from_slice(&(self.pos as u16).to_be_bytes());
+        message.extend_from_slice(&self.buf[..self.pos]);
+        stream.write_all(&message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_stop_at_the_end_of_what_arrived() {
+        let mut buffer = BytePacketBuffer {
+            buf: vec![0x12, 0x34, 0x56],
+            pos: 0,
+        };
+        assert_eq!(buffer.read_u16().unwrap(), 0x1234);
+        assert!(buffer.read_u16().is_err());
+        assert!(buffer.get_range(2, 2).is_err());
+    }
+
+    #[test]
+    fn writes_grow_up_to_the_largest_message() {
+        let mut buffer = BytePacketBuffer::new();
+        buffer.write_bytes(&vec![0; BUFFER_SIZE - 1]).unwrap();
+        buffer.write_u8(1).unwrap();
+        assert_eq!(buffer.buf.len(), BUFFER_SIZE);
+        assert!(buffer.write_u8(2).is_err());
+
+        buffer.clear();
+        buffer.write_u16(0xabcd).unwrap();
+        buffer.set_u16(0, 0x1234).unwrap();
+        assert_eq!(buffer.buf, [0x12, 0x34]);
+        assert!(buffer.set(2, 0).is_err());
+    }
 }
@@ -0,0 +1,72 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+#[derive(PartialEq, Eq, Debug, Clone, Hash, Copy, PartialOrd, Ord)]
+pub enum DnsClass {
+    UNKNOWN(u16),
+    IN,   // 1
+    CH,   // 3
+    HS,   // 4
+    NONE, // 254
+    ANY,  // 255
+}
+
+impl DnsClass {
+    pub fn to_num(&self) -> u16 {
+        match *self {
+            DnsClass::UNKNOWN(x) => x,
+            DnsClass::IN => 1,
+            DnsClass::CH => 3,
+            DnsClass::HS => 4,
+            DnsClass::NONE => 254,
+            DnsClass::ANY => 255,
+        }
+    }
+    pub fn from_num(num: u16) -> Self {
+        match num {
+            1 => DnsClass::IN,
+            3 => DnsClass::CH,
+            4 => DnsClass::HS,
+            254 => DnsClass::NONE,
+            255 => DnsClass::ANY,
+            x => DnsClass::UNKNOWN(x),
+        }
+    }
+}
+
+impl fmt::Display for DnsClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DnsClass::UNKNOWN(x) => write!(f, "CLASS{}", x),
+            DnsClass::IN => write!(f, "IN"),
+            DnsClass::CH => write!(f, "CH"),
+            DnsClass::HS => write!(f, "HS"),
+            DnsClass::NONE => write!(f, "NONE"),
+            DnsClass::ANY => write!(f, "ANY"),
+        }
+    }
+}
+
+impl FromStr for DnsClass {
+    type Err = anyhow::Error;
+
+    /// Parse a class mnemonic in any case, or the `CLASSnnn` form of
+    /// RFC 3597.
+    fn from_str(s: &str) -> Result<DnsClass> {
+        let class = match s.to_ascii_uppercase().as_str() {
+            "IN" => DnsClass::IN,
+            "CH" => DnsClass::CH,
+            "HS" => DnsClass::HS,
+            "NONE" => DnsClass::NONE,
+            "ANY" => DnsClass::ANY,
+            other => other
+                .strip_prefix("CLASS")
+                .and_then(|num| num.parse().ok())
+                .map(DnsClass::from_num)
+                .ok_or_else(|| anyhow!("unknown class {:?}", s))?,
+        };
+        Ok(class)
+    }
+}
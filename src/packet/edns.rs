@@ -0,0 +1,420 @@
+//! EDNS(0) pseudo-record handling (RFC 6891).
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::{bail, Result};
+
+use super::buffer::BytePacketBuffer;
+use super::qtype::QueryType;
+
+/// The payload size we advertise, as recommended by DNS Flag Day 2020.
+pub const DEFAULT_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+/// What every client can take over UDP, and the floor for advertised sizes.
+pub const MIN_UDP_PAYLOAD_SIZE: usize = 512;
+
+/// INFO-CODEs of Extended DNS Errors (RFC 8914, section 4).
+pub mod ede {
+    pub const OTHER: u16 = 0;
+    pub const UNSUPPORTED_DNSKEY_ALGORITHM: u16 = 1;
+    pub const UNSUPPORTED_DS_DIGEST_TYPE: u16 = 2;
+    pub const STALE_ANSWER: u16 = 3;
+    pub const FORGED_ANSWER: u16 = 4;
+    pub const DNSSEC_INDETERMINATE: u16 = 5;
+    pub const DNSSEC_BOGUS: u16 = 6;
+    pub const SIGNATURE_EXPIRED: u16 = 7;
+    pub const SIGNATURE_NOT_YET_VALID: u16 = 8;
+    pub const DNSKEY_MISSING: u16 = 9;
+    pub const RRSIGS_MISSING: u16 = 10;
+    pub const NO_ZONE_KEY_BIT_SET: u16 = 11;
+    pub const NSEC_MISSING: u16 = 12;
+    pub const CACHED_ERROR: u16 = 13;
+    pub const NOT_READY: u16 = 14;
+    pub const BLOCKED: u16 = 15;
+    pub const CENSORED: u16 = 16;
+    pub const FILTERED: u16 = 17;
+    pub const PROHIBITED: u16 = 18;
+    pub const STALE_NXDOMAIN_ANSWER: u16 = 19;
+    pub const NOT_AUTHORITATIVE: u16 = 20;
+    pub const NOT_SUPPORTED: u16 = 21;
+    pub const NO_REACHABLE_AUTHORITY: u16 = 22;
+    pub const NETWORK_ERROR: u16 = 23;
+    pub const INVALID_DATA: u16 = 24;
+
+    /// Human-readable name from the IANA registry.
+    pub fn name(code: u16) -> &'static str {
+        match code {
+            OTHER => "Other Error",
+            UNSUPPORTED_DNSKEY_ALGORITHM => "Unsupported DNSKEY Algorithm",
+            UNSUPPORTED_DS_DIGEST_TYPE => "Unsupported DS Digest Type",
+            STALE_ANSWER => "Stale Answer",
+            FORGED_ANSWER => "Forged Answer",
+            DNSSEC_INDETERMINATE => "DNSSEC Indeterminate",
+            DNSSEC_BOGUS => "DNSSEC Bogus",
+            SIGNATURE_EXPIRED => "Signature Expired",
+            SIGNATURE_NOT_YET_VALID => "Signature Not Yet Valid",
+            DNSKEY_MISSING => "DNSKEY Missing",
+            RRSIGS_MISSING => "RRSIGs Missing",
+            NO_ZONE_KEY_BIT_SET => "No Zone Key Bit Set",
+            NSEC_MISSING => "NSEC Missing",
+            CACHED_ERROR => "Cached Error",
+            NOT_READY => "Not Ready",
+            BLOCKED => "Blocked",
+            CENSORED => "Censored",
+            FILTERED => "Filtered",
+            PROHIBITED => "Prohibited",
+            STALE_NXDOMAIN_ANSWER => "Stale NXDomain Answer",
+            NOT_AUTHORITATIVE => "Not Authoritative",
+            NOT_SUPPORTED => "Not Supported",
+            NO_REACHABLE_AUTHORITY => "No Reachable Authority",
+            NETWORK_ERROR => "Network Error",
+            INVALID_DATA => "Invalid Data",
+            _ => "Unknown Error",
+        }
+    }
+}
+
+/// The network a query originates from, as carried by the EDNS Client
+/// Subnet option (RFC 7871).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientSubnet {
+    pub source_prefix: u8,
+    /// How far the answer may be reused, filled in by the answering server.
+    pub scope_prefix: u8,
+    pub address: IpAddr,
+}
+
+impl ClientSubnet {
+    /// The subnet of `address` with the given prefix length, with the host
+    /// bits cleared.
+    pub fn new(address: IpAddr, source_prefix: u8) -> ClientSubnet {
+        let source_prefix = source_prefix.min(Self::max_prefix(address));
+        ClientSubnet {
+            source_prefix,
+            scope_prefix: 0,
+            address: Self::mask(address, source_prefix),
+        }
+    }
+
+    fn max_prefix(address: IpAddr) -> u8 {
+        match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    /// Clear all but the first `prefix` bits of `address`.
+    pub fn mask(address: IpAddr, prefix: u8) -> IpAddr {
+        match address {
+            IpAddr::V4(addr) => {
+                let bits = u32::from(addr)
+                    .checked_shr(32 - prefix.min(32) as u32)
+                    .and_then(|b| b.checked_shl(32 - prefix.min(32) as u32))
+                    .unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(bits))
+            }
+            IpAddr::V6(addr) => {
+                let bits = u128::from(addr)
+                    .checked_shr(128 - prefix.min(128) as u32)
+                    .and_then(|b| b.checked_shl(128 - prefix.min(128) as u32))
+                    .unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(bits))
+            }
+        }
+    }
+
+    fn family(&self) -> u16 {
+        match self.address {
+            IpAddr::V4(_) => 1,
+            IpAddr::V6(_) => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EdnsOption {
+    ClientSubnet(ClientSubnet), // 8
+    /// DNS Cookie (RFC 7873). The server cookie is empty when the client
+    /// doesn't know one yet.
+    Cookie {
+        client: [u8; 8],
+        server: Vec<u8>,
+    }, // 10
+    ExtendedError {
+        info_code: u16,
+        extra_text: String,
+    }, // 15
+    Unknown(u16, Vec<u8>),
+}
+
+impl EdnsOption {
+    pub fn code(&self) -> u16 {
+        match *self {
+            EdnsOption::ClientSubnet(_) => 8,
+            EdnsOption::Cookie { .. } => 10,
+            EdnsOption::ExtendedError { .. } => 15,
+            EdnsOption::Unknown(code, _) => code,
+        }
+    }
+
+    pub fn read(buffer: &mut BytePacketBuffer) -> Result<EdnsOption> {
+        let code = buffer.read_u16()?;
+        let len = buffer.read_u16()? as usize;
+        let data = buffer.read_bytes(len)?;
+
+        let option = match code {
+            8 => {
+                if data.len() < 4 {
+                    bail!("Client Subnet option of {} bytes is too short", len);
+                }
+                let family = ((data[0] as u16) << 8) | data[1] as u16;
+                let source_prefix = data[2];
+                let scope_prefix = data[3];
+                let addr = &data[4..];
+
+                let address = match family {
+                    1 if addr.len() <= 4 => {
+                        let mut octets = [0; 4];
+                        octets[..addr.len()].copy_from_slice(addr);
+                        IpAddr::V4(Ipv4Addr::from(octets))
+                    }
+                    2 if addr.len() <= 16 => {
+                        let mut octets = [0; 16];
+                        octets[..addr.len()].copy_from_slice(addr);
+                        IpAddr::V6(Ipv6Addr::from(octets))
+                    }
+                    _ => bail!("malformed Client Subnet option of family {}", family),
+                };
+                let subnet = ClientSubnet {
+                    source_prefix,
+                    scope_prefix,
+                    address,
+                };
+                if source_prefix > ClientSubnet::max_prefix(address)
+                    || addr.len() != (source_prefix as usize).div_ceil(8)
+                    || ClientSubnet::mask(address, source_prefix) != address
+                {
+                    bail!("malformed Client Subnet option for /{}", source_prefix);
+                }
+                EdnsOption::ClientSubnet(subnet)
+            }
+            10 => {
+                if !(data.len() == 8 || (16..=40).contains(&data.len())) {
+                    bail!("malformed DNS Cookie option of {} bytes", len);
+                }
+                let mut client = [0; 8];
+                client.copy_from_slice(&data[..8]);
+                EdnsOption::Cookie {
+                    client,
+                    server: data[8..].to_vec(),
+                }
+            }
+            15 => {
+                if data.len() < 2 {
+                    bail!("Extended DNS Error option of {} bytes is too short", len);
+                }
+                // The text should not be NUL-terminated, but be lenient.
+                let text = String::from_utf8_lossy(&data[2..]);
+                EdnsOption::ExtendedError {
+                    info_code: ((data[0] as u16) << 8) | data[1] as u16,
+                    extra_text: text.trim_end_matches('\0').to_string(),
+                }
+            }
+            code => EdnsOption::Unknown(code, data),
+        };
+
+        Ok(option)
+    }
+
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        buffer.write_u16(self.code())?;
+
+        let pos = buffer.pos();
+        buffer.write_u16(0)?;
+
+        match self {
+            EdnsOption::ClientSubnet(subnet) => {
+                buffer.write_u16(subnet.family())?;
+                buffer.write_u8(subnet.source_prefix)?;
+                buffer.write_u8(subnet.scope_prefix)?;
+
+                // only the significant octets of the address are sent
+                let octets = match ClientSubnet::mask(subnet.address, subnet.source_prefix) {
+                    IpAddr::V4(addr) => addr.octets().to_vec(),
+                    IpAddr::V6(addr) => addr.octets().to_vec(),
+                };
+                buffer.write_bytes(&octets[..(subnet.source_prefix as usize).div_ceil(8)])?;
+            }
+            EdnsOption::Cookie { client, server } => {
+                buffer.write_bytes(client)?;
+                buffer.write_bytes(server)?;
+            }
+            EdnsOption::ExtendedError {
+                info_code,
+                extra_text,
+            } => {
+                buffer.write_u16(*info_code)?;
+                buffer.write_bytes(extra_text.as_bytes())?;
+            }
+            EdnsOption::Unknown(_, data) => buffer.write_bytes(data)?,
+        }
+
+        let size = buffer.pos() - (pos + 2);
+        buffer.set_u16(pos, size as u16)
+    }
+}
+
+/// The contents of an OPT record. It travels in the additional section, but
+/// describes the message rather than any name, so `DnsPacket` keeps it apart
+/// from the other records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edns {
+    pub udp_payload_size: u16,
+    /// Upper eight bits of the 12-bit RCODE.
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+impl Default for Edns {
+    fn default() -> Self {
+        Edns {
+            udp_payload_size: DEFAULT_UDP_PAYLOAD_SIZE,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+}
+
+impl Edns {
+    /// The Extended DNS Errors carried in this record.
+    pub fn extended_errors(&self) -> impl Iterator<Item = &EdnsOption> {
+        self.options
+            .iter()
+            .filter(|o| matches!(o, EdnsOption::ExtendedError { .. }))
+    }
+
+    /// The client and server cookie, if the message carries a DNS Cookie.
+    pub fn cookie(&self) -> Option<(&[u8; 8], &[u8])> {
+        self.options.iter().find_map(|o| match o {
+            EdnsOption::Cookie { client, server } => Some((client, &server[..])),
+            _ => None,
+        })
+    }
+
+    /// The EDNS Client Subnet, if the message carries one.
+    pub fn client_subnet(&self) -> Option<&ClientSubnet> {
+        self.options.iter().find_map(|o| match o {
+            EdnsOption::ClientSubnet(subnet) => Some(subnet),
+            _ => None,
+        })
+    }
+
+    /// Whether the record at the buffer's position is an OPT record, whose
+    /// owner is always the root.
+    pub fn is_next(buffer: &mut BytePacketBuffer) -> Result<bool> {
+        let pos = buffer.pos();
+        let opt = QueryType::OPT.to_num();
+        Ok(buffer.get(pos)? == 0
+            && buffer.get(pos + 1)? == (opt >> 8) as u8
+            && buffer.get(pos + 2)? == (opt & 0xff) as u8)
+    }
+
+    pub fn read(buffer: &mut BytePacketBuffer) -> Result<Edns> {
+        let owner = buffer.read_qname()?;
+        if !owner.is_empty() {
+            bail!("OPT record owned by {} instead of the root", owner);
+        }
+        let _ = buffer.read_u16()?; // type
+
+        // The class and TTL fields are reused for EDNS parameters.
+        let udp_payload_size = buffer.read_u16()?;
+        let extended_rcode = buffer.read()?;
+        let version = buffer.read()?;
+        let flags = buffer.read_u16()?;
+
+        let data_len = buffer.read_u16()?;
+        let data_end = buffer.pos() + data_len as usize;
+        let mut options = Vec::new();
+        while buffer.pos() < data_end {
+            options.push(EdnsOption::read(buffer)?);
+        }
+        if buffer.pos() != data_end {
+            bail!("EDNS options overrun the OPT record");
+        }
+
+        Ok(Edns {
+            udp_payload_size,
+            extended_rcode,
+            version,
+            dnssec_ok: flags & 0x8000 > 0,
+            options,
+        })
+    }
+
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        buffer.write_u8(0)?;
+        buffer.write_u16(QueryType::OPT.to_num())?;
+        buffer.write_u16(self.udp_payload_size)?;
+        buffer.write_u8(self.extended_rcode)?;
+        buffer.write_u8(self.version)?;
+        buffer.write_u16(if self.dnssec_ok { 0x8000 } else { 0 })?;
+
+        let pos = buffer.pos();
+        buffer.write_u16(0)?;
+        for option in &self.options {
+            option.write(buffer)?;
+        }
+
+        let size = buffer.pos() - (pos + 2);
+        buffer.set_u16(pos, size as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_option(code: u16, data: &[u8]) -> Result<EdnsOption> {
+        let mut buffer = BytePacketBuffer::new();
+        buffer.write_u16(code)?;
+        buffer.write_u16(data.len() as u16)?;
+        buffer.write_bytes(data)?;
+        buffer.seek(0)?;
+        EdnsOption::read(&mut buffer)
+    }
+
+    #[test]
+    fn reads_client_subnet() {
+        let option = read_option(8, &[0, 1, 24, 0, 192, 0, 2]).unwrap();
+        assert_eq!(
+            option,
+            EdnsOption::ClientSubnet(ClientSubnet {
+                source_prefix: 24,
+                scope_prefix: 0,
+                address: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_client_subnet() {
+        // longer than an IPv4 address
+        assert!(read_option(8, &[0, 1, 33, 0, 192, 0, 2, 1, 0]).is_err());
+        // bits set beyond the prefix
+        assert!(read_option(8, &[0, 1, 24, 0, 192, 0, 2, 1]).is_err());
+        // more address octets than the prefix needs
+        assert!(read_option(8, &[0, 1, 8, 0, 192, 0]).is_err());
+    }
+
+    #[test]
+    fn rejects_cookies_of_the_wrong_length() {
+        assert!(read_option(10, b"abc").is_err());
+        assert!(read_option(10, b"0123456789").is_err());
+        assert!(read_option(10, &[0; 41]).is_err());
+        assert!(read_option(10, b"01234567").is_ok());
+    }
+}
@@ -0,0 +1,261 @@
+//! Helpers for turning raw rdata into its presentation (zone-file) form and
+//! back.
+
+use std::fmt::Write;
+
+use anyhow::{anyhow, bail, Result};
+
+/// Encode bytes as upper-case hexadecimal, the way SSHFP and TLSA data is
+/// usually shown.
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(out, "{:02X}", b);
+    }
+    out
+}
+
+/// Render a <character-string> in double quotes, escaping quotes and
+/// backslashes and writing non-printable bytes as `\DDD`.
+pub fn quote_character_string(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() + 2);
+    out.push('"');
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(b as char);
+            }
+            0x20..=0x7e => out.push(b as char),
+            _ => {
+                let _ = write!(out, "\\{:03}", b);
+            }
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Decode hexadecimal in either case, the inverse of [`to_hex`].
+pub fn from_hex(text: &str) -> Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("invalid hex string {:?}", text);
+    }
+    let digit = |b: u8| (b as char).to_digit(16).unwrap_or(0) as u8;
+    Ok(text
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| (digit(pair[0]) << 4) | digit(pair[1]))
+        .collect())
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode bytes as padded standard base64 (RFC 4648), as used by the `ech`
+/// SvcParam.
+pub fn to_base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | (b[2] as u32);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decode padded or unpadded standard base64, the inverse of [`to_base64`].
+/// Whitespace is not allowed; join the pieces before decoding.
+pub fn from_base64(text: &str) -> Result<Vec<u8>> {
+    let trimmed = text.trim_end_matches('=');
+    if text.len() - trimmed.len() > 2 || trimmed.len() % 4 == 1 {
+        bail!("invalid base64 string {:?}", text);
+    }
+
+    let mut out = Vec::with_capacity(trimmed.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in trimmed.bytes() {
+        let value = match BASE64_ALPHABET.iter().position(|&a| a == c) {
+            Some(value) => value as u32,
+            None => bail!("invalid base64 string {:?}", text),
+        };
+        acc = (acc << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Ok(out)
+}
+
+/// One whitespace-separated field of a master-file entry. Escapes are kept
+/// as written; `quoted` tells whether any part of it was in double quotes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    pub quoted: bool,
+}
+
+/// Split presentation-format text into fields (RFC 1035, section 5.1).
+///
+/// Quoted strings may hold whitespace, a `;` starts a comment running to the
+/// end of the line, and parentheses only group lines, so they are dropped.
+pub fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut current: Option<Token> = None;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let escaped = match chars.next() {
+                    Some(escaped) => escaped,
+                    None => bail!("dangling backslash at the end of {:?}", text),
+                };
+                let token = current.get_or_insert_with(Token::empty);
+                token.text.push('\\');
+                token.text.push(escaped);
+            }
+            '"' => {
+                let token = current.get_or_insert_with(Token::empty);
+                token.quoted = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            token.text.push('\\');
+                            match chars.next() {
+                                Some(escaped) => token.text.push(escaped),
+                                None => bail!("unterminated quoted string in {:?}", text),
+                            }
+                        }
+                        Some(c) => token.text.push(c),
+                        None => bail!("unterminated quoted string in {:?}", text),
+                    }
+                }
+            }
+            ';' => {
+                tokens.extend(current.take());
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '(' | ')' => tokens.extend(current.take()),
+            c if c.is_whitespace() => tokens.extend(current.take()),
+            c => current.get_or_insert_with(Token::empty).text.push(c),
+        }
+    }
+    tokens.extend(current);
+
+    Ok(tokens)
+}
+
+impl Token {
+    fn empty() -> Token {
+        Token {
+            text: String::new(),
+            quoted: false,
+        }
+    }
+}
+
+/// Resolve the `\X` and `\DDD` escapes of presentation-format text into the
+/// bytes they stand for.
+pub fn unescape(text: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(d) if d.is_ascii_digit() => {
+                let digits = [Some(d), bytes.next(), bytes.next()];
+                let mut value = 0u32;
+                for digit in digits {
+                    match digit {
+                        Some(digit) if digit.is_ascii_digit() => {
+                            value = value * 10 + (digit - b'0') as u32;
+                        }
+                        _ => bail!("\\DDD escape needs three digits in {:?}", text),
+                    }
+                }
+                if value > 255 {
+                    bail!("escape \\{:03} is out of range in {:?}", value, text);
+                }
+                out.push(value as u8);
+            }
+            Some(escaped) => out.push(escaped),
+            None => bail!("dangling backslash at the end of {:?}", text),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Parse a <character-string>, quoted or not, into at most 255 bytes.
+pub fn parse_character_string(token: &Token) -> Result<Vec<u8>> {
+    let bytes = unescape(&token.text)?;
+    if bytes.len() > 0xff {
+        bail!("character-string of {} bytes exceeds 255", bytes.len());
+    }
+    Ok(bytes)
+}
+
+/// Parse a TTL or SOA timer: plain seconds, or the `1w2d3h4m5s` unit
+/// notation BIND accepts.
+pub fn parse_ttl(text: &str) -> Result<u32> {
+    if let Ok(seconds) = text.parse() {
+        return Ok(seconds);
+    }
+    if text.is_empty() {
+        bail!("empty TTL");
+    }
+
+    let mut total = 0u32;
+    let mut digits = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            'w' => 604_800,
+            'd' => 86_400,
+            'h' => 3_600,
+            'm' => 60,
+            's' => 1,
+            _ => bail!("invalid TTL {:?}", text),
+        };
+        let count: u32 = match digits.parse() {
+            Ok(count) => count,
+            Err(_) => bail!("invalid TTL {:?}", text),
+        };
+        total = count
+            .checked_mul(unit)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(|| anyhow!("TTL {:?} is too large", text))?;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        bail!("invalid TTL {:?}", text);
+    }
+    Ok(total)
+}
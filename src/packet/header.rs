@@ -1,5 +1,5 @@
 use super::Result;
-use super::{buffer::BytePacketBuffer, rscode::ResultCode};
+use super::{buffer::BytePacketBuffer, opcode::Opcode, rscode::ResultCode};
 
 #[derive(Clone, Debug)]
 pub struct DnsHeader {
@@ -7,10 +7,10 @@ pub struct DnsHeader {
     pub recursion_desired: bool,    // 1 bit
     pub truncated_message: bool,    // 1 bit
     pub authoritative_answer: bool, // 1 bit
-    pub opcode: u8,                 // 4 bits
+    pub opcode: Opcode,             // 4 bits
     pub response: bool,             // 1 bit
 
-    pub rescode: ResultCode,       // 4 bits
+    pub rescode: ResultCode,       // 4 bits, 12 with EDNS
     pub checking_disabled: bool,   // 1 bit
     pub authed_data: bool,         // 1 bit
     pub z: bool,                   // 1 bit
@@ -30,7 +30,7 @@ impl Default for DnsHeader {
             recursion_desired: false,
             truncated_message: false,
             authoritative_answer: false,
-            opcode: 0,
+            opcode: Opcode::QUERY,
             response: false,
 
             rescode: ResultCode::NOERROR,
@@ -62,10 +62,10 @@ impl DnsHeader {
         self.recursion_desired = (a & 1) > 0;
         self.truncated_message = (a & (1 << 1)) > 0;
         self.authoritative_answer = (a & (1 << 2)) > 0;
-        self.opcode = (a >> 3) & 0x0F;
+        self.opcode = Opcode::from_num((a >> 3) & 0x0F);
         self.response = (a & (1 << 7)) > 0;
 
-        self.rescode = ResultCode::from_num(b & 0x0F);
+        self.rescode = ResultCode::from_num((b & 0x0F) as u16);
         self.checking_disabled = (b & (1 << 4)) > 0;
         self.authed_data = (b & (1 << 5)) > 0;
         self.z = (b & (1 << 6)) > 0;
@@ -86,9 +86,9 @@ impl DnsHeader {
         let a = (self.recursion_desired as u8)
             | ((self.truncated_message as u8) << 1)
             | ((self.authoritative_answer as u8) << 2)
-            | (self.opcode << 3)
+            | ((self.opcode.to_num() & 0x0F) << 3)
             | ((self.response as u8) << 7);
-        let b = (self.rescode as u8)
+        let b = ((self.rescode.to_num() & 0x0F) as u8)
             | ((self.checking_disabled as u8) << 4)
             | ((self.authed_data as u8) << 5)
             | ((self.z as u8) << 6)
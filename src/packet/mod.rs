@@ -1,16 +1,25 @@
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 pub mod buffer;
+pub mod class;
+pub mod edns;
+pub mod encoding;
 pub mod header;
+pub mod name;
+pub mod opcode;
 pub mod qtype;
 pub mod question;
 pub mod record;
 pub mod rscode;
+pub mod svcb;
+pub mod tsig;
+
+use crate::zone::update;
 
 use self::{
-    buffer::BytePacketBuffer, header::DnsHeader, qtype::QueryType, question::DnsQuestion,
-    record::DnsRecord,
+    buffer::BytePacketBuffer, edns::Edns, header::DnsHeader, opcode::Opcode, qtype::QueryType,
+    question::DnsQuestion, record::DnsRecord, rscode::ResultCode, tsig::Tsig,
 };
 
 #[derive(Clone, Debug)]
@@ -20,6 +29,19 @@ pub struct DnsPacket {
     pub answers: Vec<DnsRecord>,
     pub authorities: Vec<DnsRecord>,
     pub resources: Vec<DnsRecord>,
+    /// The OPT pseudo-record, which is read from and written to the end of
+    /// the additional section.
+    pub edns: Option<Edns>,
+    /// The signature of the message, read from the end of the additional
+    /// section. It is not written by `write`, as it covers the written
+    /// message: see `Tsig::write`.
+    pub tsig: Option<Tsig>,
+}
+
+impl Default for DnsPacket {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DnsPacket {
@@ -30,6 +52,8 @@ impl DnsPacket {
             answers: Vec::new(),
             authorities: Vec::new(),
             resources: Vec::new(),
+            edns: None,
+            tsig: None,
         }
     }
 
@@ -43,21 +67,46 @@ impl DnsPacket {
             result.questions.push(question);
         }
 
+        // the prerequisite and update sections of an UPDATE may leave out rdata
+        let read_record = match result.header.opcode {
+            Opcode::UPDATE => update::read_record,
+            _ => DnsRecord::read,
+        };
+
         for _ in 0..result.header.answers {
-            let rec = DnsRecord::read(buffer)?;
+            let rec = read_record(buffer)?;
             result.answers.push(rec);
         }
 
         for _ in 0..result.header.authoritative_entries {
-            let rec = DnsRecord::read(buffer)?;
+            let rec = read_record(buffer)?;
             result.authorities.push(rec);
         }
 
-        for _ in 0..result.header.resource_entries {
+        for i in 0..result.header.resource_entries {
+            if Tsig::is_next(buffer)? {
+                if i + 1 != result.header.resource_entries {
+                    bail!("TSIG record is not the last one in message");
+                }
+                result.tsig = Some(Tsig::read(buffer)?);
+                continue;
+            }
+            if Edns::is_next(buffer)? {
+                if result.edns.is_some() {
+                    bail!("more than one OPT record in message");
+                }
+                result.edns = Some(Edns::read(buffer)?);
+                continue;
+            }
             let rec = DnsRecord::read(buffer)?;
             result.resources.push(rec);
         }
 
+        if let Some(edns) = &result.edns {
+            let rescode = ((edns.extended_rcode as u16) << 4) | result.header.rescode.to_num();
+            result.header.rescode = ResultCode::from_num(rescode);
+        }
+
         Ok(result)
     }
 
@@ -65,7 +114,18 @@ impl DnsPacket {
         self.header.questions = self.questions.len() as u16;
         self.header.answers = self.answers.len() as u16;
         self.header.authoritative_entries = self.authorities.len() as u16;
-        self.header.resource_entries = self.resources.len() as u16;
+        // Only the lower four bits of the RCODE fit in the header, the rest
+        // goes into the OPT record. A message without one, such as the
+        // answer to a query without EDNS, may not gain one (RFC 6891,
+        // section 7), so an extended RCODE can only say the server failed.
+        if self.header.rescode.to_num() > 0x0F && self.edns.is_none() {
+            self.header.rescode = ResultCode::SERVFAIL;
+        }
+        let rescode = self.header.rescode.to_num();
+        if let Some(edns) = &mut self.edns {
+            edns.extended_rcode = (rescode >> 4) as u8;
+        }
+        self.header.resource_entries = (self.resources.len() + self.edns.is_some() as usize) as u16;
 
         self.header.write(buffer)?;
 
@@ -84,9 +144,39 @@ impl DnsPacket {
         for rec in &self.resources {
             rec.write(buffer)?;
         }
+
+        if let Some(edns) = &self.edns {
+            edns.write(buffer)?;
+        }
         Ok(())
     }
 
+    /// Write the packet in at most `max_size` octets. Additional records are
+    /// left out first, then the authority section; if the answers alone do
+    /// not fit either, they are dropped and the TC bit is set so that the
+    /// client retries over TCP.
+    pub fn write_within(&mut self, buffer: &mut BytePacketBuffer, max_size: usize) -> Result<()> {
+        loop {
+            buffer.clear();
+            self.write(buffer)?;
+            if buffer.pos() <= max_size {
+                return Ok(());
+            }
+
+            if self.resources.pop().is_some() {
+                continue;
+            }
+            if !self.authorities.is_empty() {
+                self.authorities.clear();
+            } else if !self.answers.is_empty() {
+                self.answers.clear();
+                self.header.truncated_message = true;
+            } else {
+                bail!("message does not fit in {} bytes", max_size);
+            }
+        }
+    }
+
     // randomly pick up an A record in Addtional Section from upstream.
     pub fn pick_one_server(&self) -> Option<IpAddr> {
         self.answers
@@ -116,7 +206,7 @@ impl DnsPacket {
             })
             .next()
     }
-    fn get_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = (&String, &String)> {
+    fn get_ns<'a>(&'a self, qname: &'a str) -> impl Iterator<Item = (&'a String, &'a String)> {
         self.authorities
             .iter()
             .filter_map(|record| match record {
@@ -124,7 +214,7 @@ impl DnsPacket {
                 _ => None,
             })
             // discard servers not authoritative to the query.
-            .filter(|(domain, _)| qname.ends_with(*domain))
+            .filter(|(domain, _)| name::is_subdomain(qname, domain))
     }
 
     pub fn get_resolved_ns(&self, qname: &str) -> Option<IpAddr> {
@@ -133,10 +223,10 @@ impl DnsPacket {
                 self.resources
                     .iter()
                     .filter_map(move |record| match record {
-                        DnsRecord::A { domain, addr, .. } if domain == host => {
+                        DnsRecord::A { domain, addr, .. } if name::eq(domain, host) => {
                             Some(IpAddr::V4(*addr))
                         }
-                        DnsRecord::AAAA { domain, addr, .. } if domain == host => {
+                        DnsRecord::AAAA { domain, addr, .. } if name::eq(domain, host) => {
                             Some(IpAddr::V6(*addr))
                         }
                         _ => None,
@@ -144,7 +234,135 @@ impl DnsPacket {
             })
             .next()
     }
-    pub fn get_unresolved_ns<'a>(&'a self, qname: &'a str) -> Option<&String> {
+    pub fn get_unresolved_ns<'a>(&'a self, qname: &'a str) -> Option<&'a String> {
         self.get_ns(qname).map(|(_, host)| host).next()
     }
+
+    /// Follow the CNAME/DNAME chain for `qname` through the answer section,
+    /// inserting the CNAMEs implied by DNAME records where the server left
+    /// them out.
+    ///
+    /// Returns the name at the end of the chain when a DNAME took part in it
+    /// and the answer section holds nothing for that name yet, i.e. when the
+    /// caller still has to resolve it.
+    pub fn apply_dname(&mut self, qname: &str) -> Result<Option<String>> {
+        let mut current = qname.to_string();
+        let mut saw_dname = false;
+
+        // every step consumes an alias, so a chain can't be longer than this
+        for _ in 0..=self.answers.len() {
+            let cname = self.answers.iter().find_map(|rec| match rec {
+                DnsRecord::CNAME { domain, host, .. } if name::eq(domain, &current) => {
+                    Some(host.clone())
+                }
+                _ => None,
+            });
+            if let Some(host) = cname {
+                current = host;
+                continue;
+            }
+
+            let mut synthesized = None;
+            for (i, rec) in self.answers.iter().enumerate() {
+                if let Some(cname) = rec.synthesize_cname(&current)? {
+                    synthesized = Some((i, cname));
+                    break;
+                }
+            }
+            match synthesized {
+                Some((i, cname)) => {
+                    saw_dname = true;
+                    self.answers.insert(i + 1, cname);
+                }
+                None => break,
+            }
+        }
+
+        let resolved = self
+            .answers
+            .iter()
+            .any(|rec| name::eq(rec.domain(), &current));
+        if saw_dname && !resolved {
+            Ok(Some(current))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::class::DnsClass;
+
+    fn a(domain: &str, last: u8) -> DnsRecord {
+        DnsRecord::A {
+            domain: domain.to_string(),
+            class: DnsClass::IN,
+            addr: Ipv4Addr::new(192, 0, 2, last),
+            ttl: 300,
+        }
+    }
+
+    #[test]
+    fn drops_sections_until_the_message_fits() {
+        let mut packet = DnsPacket::new();
+        packet
+            .questions
+            .push(DnsQuestion::new("example.com.".to_string(), QueryType::A));
+        packet.answers.push(a("example.com.", 1));
+        for i in 0..40 {
+            packet.resources.push(a("ns.example.com.", i));
+        }
+
+        let mut buffer = BytePacketBuffer::new();
+        packet.write_within(&mut buffer, 512).unwrap();
+        assert!(buffer.pos() <= 512);
+        assert_eq!(packet.answers.len(), 1);
+        assert!(!packet.header.truncated_message);
+
+        buffer.seek(0).unwrap();
+        let written = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert_eq!(written.resources.len(), packet.resources.len());
+    }
+
+    #[test]
+    fn keeps_extended_rcodes_only_with_edns() {
+        let mut packet = DnsPacket::new();
+        packet.header.response = true;
+        packet.header.rescode = ResultCode::BADCOOKIE;
+        let mut buffer = BytePacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.seek(0).unwrap();
+        let written = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert!(written.edns.is_none());
+        assert_eq!(written.header.rescode, ResultCode::SERVFAIL);
+
+        let mut packet = DnsPacket::new();
+        packet.header.response = true;
+        packet.header.rescode = ResultCode::BADCOOKIE;
+        packet.edns = Some(Edns::default());
+        let mut buffer = BytePacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer.seek(0).unwrap();
+        let written = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert_eq!(written.header.rescode, ResultCode::BADCOOKIE);
+    }
+
+    #[test]
+    fn sets_tc_when_the_answers_do_not_fit() {
+        let mut packet = DnsPacket::new();
+        packet
+            .questions
+            .push(DnsQuestion::new("example.com.".to_string(), QueryType::A));
+        for i in 0..40 {
+            packet.answers.push(a("example.com.", i));
+        }
+
+        let mut buffer = BytePacketBuffer::new();
+        packet.write_within(&mut buffer, 512).unwrap();
+        assert!(packet.header.truncated_message);
+        assert!(packet.answers.is_empty());
+        assert!(buffer.pos() <= 512);
+    }
 }
@@ -0,0 +1,170 @@
+//! Comparisons on domain names that respect label boundaries and ignore
+//! case and the trailing root dot.
+//!
+//! Names are kept in presentation form: labels joined by dots, with dots,
+//! backslashes and other special bytes inside a label escaped as `\X` or
+//! `\DDD`, and the root written as the empty string.
+
+use std::fmt::Write;
+
+use anyhow::{bail, Result};
+
+use super::encoding::unescape;
+
+/// Split a name into its labels, dropping the empty root label. A dot
+/// escaped with a backslash does not end a label.
+pub fn labels(name: &str) -> Vec<&str> {
+    let mut labels = split(name);
+    labels.retain(|l| !l.is_empty());
+    labels
+}
+
+/// Split a name at its unescaped dots, keeping empty pieces.
+fn split(name: &str) -> Vec<&str> {
+    let mut labels = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, b) in name.bytes().enumerate() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'.' => {
+                labels.push(&name[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    labels.push(&name[start..]);
+    labels
+}
+
+/// Whether two names are the same, ignoring case and a trailing dot.
+pub fn eq(a: &str, b: &str) -> bool {
+    let a = labels(a);
+    let b = labels(b);
+    a.len() == b.len() && a.iter().zip(&b).all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+/// Whether `name` is `ancestor` or lies below it.
+pub fn is_subdomain(name: &str, ancestor: &str) -> bool {
+    let name = labels(name);
+    let ancestor = labels(ancestor);
+    name.len() >= ancestor.len()
+        && name[name.len() - ancestor.len()..]
+            .iter()
+            .zip(&ancestor)
+            .all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+/// Whether `name` lies strictly below `ancestor`.
+pub fn is_proper_subdomain(name: &str, ancestor: &str) -> bool {
+    is_subdomain(name, ancestor) && labels(name).len() > labels(ancestor).len()
+}
+
+/// Replace the `suffix` of `name` by `replacement`, returning a fully
+/// qualified name. `name` must lie below `suffix`.
+pub fn replace_suffix(name: &str, suffix: &str, replacement: &str) -> String {
+    let name = labels(name);
+    let keep = name.len() - labels(suffix).len();
+    let mut out = String::new();
+    for label in name[..keep].iter().chain(labels(replacement).iter()) {
+        out.push_str(label);
+        out.push('.');
+    }
+    out
+}
+
+/// Length of the name in wire format, which must not exceed 255 octets.
+pub fn wire_len(name: &str) -> usize {
+    labels(name)
+        .iter()
+        .map(|l| unescape(l).map_or(l.len(), |bytes| bytes.len()) + 1)
+        .sum::<usize>()
+        + 1
+}
+
+/// The canonical form of a name: lower case and fully qualified.
+pub fn normalize(name: &str) -> String {
+    let mut out = String::with_capacity(name.len() + 1);
+    for label in labels(name) {
+        out.push_str(&label.to_ascii_lowercase());
+        out.push('.');
+    }
+    out
+}
+
+/// The name as it is written in a master file, `.` for the root.
+pub fn or_root(name: &str) -> &str {
+    if name.is_empty() {
+        "."
+    } else {
+        name
+    }
+}
+
+/// Write the raw bytes of a label in presentation form, escaping the
+/// characters that are special in master files.
+pub fn escape_label(label: &[u8]) -> String {
+    let mut out = String::with_capacity(label.len());
+    for &b in label {
+        match b {
+            b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                out.push('\\');
+                out.push(b as char);
+            }
+            0x21..=0x7e => out.push(b as char),
+            _ => {
+                let _ = write!(out, "\\{:03}", b);
+            }
+        }
+    }
+    out
+}
+
+/// The raw bytes of a label in presentation form.
+pub fn unescape_label(label: &str) -> Result<Vec<u8>> {
+    let bytes = unescape(label)?;
+    if bytes.is_empty() || bytes.len() > 63 {
+        bail!("label {:?} must be 1 to 63 octets long", label);
+    }
+    Ok(bytes)
+}
+
+/// Parse a name as written in a master file, returning it fully qualified
+/// and with canonical escapes.
+///
+/// `@` stands for `origin`, and a name without a trailing dot is taken
+/// relative to it. `origin` must itself be fully qualified, or empty for
+/// the root.
+pub fn from_text(text: &str, origin: &str) -> Result<String> {
+    if text == "@" {
+        return Ok(origin.to_string());
+    }
+    if text == "." {
+        return Ok(String::new());
+    }
+
+    let mut parts = split(text);
+    let absolute = parts.len() > 1 && parts.last() == Some(&"");
+    if absolute {
+        parts.pop();
+    }
+    if parts.iter().any(|l| l.is_empty()) {
+        bail!("{:?} is not a valid domain name", text);
+    }
+
+    let mut out = String::with_capacity(text.len() + origin.len() + 1);
+    for label in parts {
+        out.push_str(&escape_label(&unescape_label(label)?));
+        out.push('.');
+    }
+    if !absolute {
+        out.push_str(origin);
+    }
+
+    if wire_len(&out) > 255 {
+        bail!("{:?} is longer than 255 octets", text);
+    }
+    Ok(out)
+}
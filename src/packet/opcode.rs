@@ -0,0 +1,35 @@
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    UNKNOWN(u8),
+    QUERY,  // 0
+    IQUERY, // 1, obsolete
+    STATUS, // 2
+    NOTIFY, // 4
+    UPDATE, // 5
+    DSO,    // 6
+}
+
+impl Opcode {
+    pub fn to_num(&self) -> u8 {
+        match *self {
+            Opcode::UNKNOWN(x) => x,
+            Opcode::QUERY => 0,
+            Opcode::IQUERY => 1,
+            Opcode::STATUS => 2,
+            Opcode::NOTIFY => 4,
+            Opcode::UPDATE => 5,
+            Opcode::DSO => 6,
+        }
+    }
+    pub fn from_num(num: u8) -> Opcode {
+        match num {
+            0 => Opcode::QUERY,
+            1 => Opcode::IQUERY,
+            2 => Opcode::STATUS,
+            4 => Opcode::NOTIFY,
+            5 => Opcode::UPDATE,
+            6 => Opcode::DSO,
+            x => Opcode::UNKNOWN(x),
+        }
+    }
+}
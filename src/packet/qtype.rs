@@ -1,3 +1,8 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
 #[derive(PartialEq, Eq, Debug, Clone, Hash, Copy)]
 pub enum QueryType {
     UNKNOWN(u16),
@@ -5,8 +10,26 @@ pub enum QueryType {
     NS,    // 2
     CNAME, // 5
     SOA,   // 6
+    PTR,   // 12
+    HINFO, // 13
     MX,    // 15
+    TXT,   // 16
+    RP,    // 17
     AAAA,  // 28
+    LOC,   // 29
+    NAPTR, // 35
+    DNAME, // 39
+    OPT,   // 41
+    SSHFP, // 44
+    TLSA,  // 52
+    SVCB,  // 64
+    HTTPS, // 65
+    TSIG,  // 250
+    IXFR,  // 251
+    AXFR,  // 252
+    ANY,   // 255
+    URI,   // 256
+    CAA,   // 257
 }
 
 impl QueryType {
@@ -17,8 +40,26 @@ impl QueryType {
             QueryType::NS => 2,
             QueryType::CNAME => 5,
             QueryType::SOA => 6,
+            QueryType::PTR => 12,
+            QueryType::HINFO => 13,
             QueryType::MX => 15,
+            QueryType::TXT => 16,
+            QueryType::RP => 17,
             QueryType::AAAA => 28,
+            QueryType::LOC => 29,
+            QueryType::NAPTR => 35,
+            QueryType::DNAME => 39,
+            QueryType::OPT => 41,
+            QueryType::SSHFP => 44,
+            QueryType::TLSA => 52,
+            QueryType::SVCB => 64,
+            QueryType::HTTPS => 65,
+            QueryType::TSIG => 250,
+            QueryType::IXFR => 251,
+            QueryType::AXFR => 252,
+            QueryType::ANY => 255,
+            QueryType::URI => 256,
+            QueryType::CAA => 257,
         }
     }
     pub fn from_num(num: u16) -> Self {
@@ -27,9 +68,99 @@ impl QueryType {
             2 => QueryType::NS,
             5 => QueryType::CNAME,
             6 => QueryType::SOA,
+            12 => QueryType::PTR,
+            13 => QueryType::HINFO,
             15 => QueryType::MX,
+            16 => QueryType::TXT,
+            17 => QueryType::RP,
             28 => QueryType::AAAA,
+            29 => QueryType::LOC,
+            35 => QueryType::NAPTR,
+            39 => QueryType::DNAME,
+            41 => QueryType::OPT,
+            44 => QueryType::SSHFP,
+            52 => QueryType::TLSA,
+            64 => QueryType::SVCB,
+            65 => QueryType::HTTPS,
+            250 => QueryType::TSIG,
+            251 => QueryType::IXFR,
+            252 => QueryType::AXFR,
+            255 => QueryType::ANY,
+            256 => QueryType::URI,
+            257 => QueryType::CAA,
             x => QueryType::UNKNOWN(x),
         }
     }
 }
+
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            QueryType::UNKNOWN(x) => write!(f, "TYPE{}", x),
+            QueryType::A => write!(f, "A"),
+            QueryType::NS => write!(f, "NS"),
+            QueryType::CNAME => write!(f, "CNAME"),
+            QueryType::SOA => write!(f, "SOA"),
+            QueryType::PTR => write!(f, "PTR"),
+            QueryType::HINFO => write!(f, "HINFO"),
+            QueryType::MX => write!(f, "MX"),
+            QueryType::TXT => write!(f, "TXT"),
+            QueryType::RP => write!(f, "RP"),
+            QueryType::AAAA => write!(f, "AAAA"),
+            QueryType::LOC => write!(f, "LOC"),
+            QueryType::NAPTR => write!(f, "NAPTR"),
+            QueryType::DNAME => write!(f, "DNAME"),
+            QueryType::OPT => write!(f, "OPT"),
+            QueryType::SSHFP => write!(f, "SSHFP"),
+            QueryType::TLSA => write!(f, "TLSA"),
+            QueryType::SVCB => write!(f, "SVCB"),
+            QueryType::HTTPS => write!(f, "HTTPS"),
+            QueryType::TSIG => write!(f, "TSIG"),
+            QueryType::IXFR => write!(f, "IXFR"),
+            QueryType::AXFR => write!(f, "AXFR"),
+            QueryType::ANY => write!(f, "ANY"),
+            QueryType::URI => write!(f, "URI"),
+            QueryType::CAA => write!(f, "CAA"),
+        }
+    }
+}
+
+impl FromStr for QueryType {
+    type Err = anyhow::Error;
+
+    /// Parse a type mnemonic in any case, or the `TYPEnnn` form of RFC 3597.
+    fn from_str(s: &str) -> Result<QueryType> {
+        let qtype = match s.to_ascii_uppercase().as_str() {
+            "A" => QueryType::A,
+            "NS" => QueryType::NS,
+            "CNAME" => QueryType::CNAME,
+            "SOA" => QueryType::SOA,
+            "PTR" => QueryType::PTR,
+            "HINFO" => QueryType::HINFO,
+            "MX" => QueryType::MX,
+            "TXT" => QueryType::TXT,
+            "RP" => QueryType::RP,
+            "AAAA" => QueryType::AAAA,
+            "LOC" => QueryType::LOC,
+            "NAPTR" => QueryType::NAPTR,
+            "DNAME" => QueryType::DNAME,
+            "OPT" => QueryType::OPT,
+            "SSHFP" => QueryType::SSHFP,
+            "TLSA" => QueryType::TLSA,
+            "SVCB" => QueryType::SVCB,
+            "HTTPS" => QueryType::HTTPS,
+            "TSIG" => QueryType::TSIG,
+            "IXFR" => QueryType::IXFR,
+            "AXFR" => QueryType::AXFR,
+            "ANY" => QueryType::ANY,
+            "URI" => QueryType::URI,
+            "CAA" => QueryType::CAA,
+            other => other
+                .strip_prefix("TYPE")
+                .and_then(|num| num.parse().ok())
+                .map(QueryType::from_num)
+                .ok_or_else(|| anyhow!("unknown record type {:?}", s))?,
+        };
+        Ok(qtype)
+    }
+}
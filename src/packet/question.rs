@@ -1,22 +1,27 @@
 use super::Result;
-use super::{buffer::BytePacketBuffer, qtype::QueryType};
+use super::{buffer::BytePacketBuffer, class::DnsClass, qtype::QueryType};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DnsQuestion {
     pub name: String,
     pub qtype: QueryType,
+    pub class: DnsClass,
 }
 
 impl DnsQuestion {
+    /// A question in the Internet class.
     pub fn new(name: String, qtype: QueryType) -> Self {
-        DnsQuestion { name, qtype }
+        DnsQuestion {
+            name,
+            qtype,
+            class: DnsClass::IN,
+        }
     }
 
     pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
         self.name = buffer.read_qname()?;
         self.qtype = QueryType::from_num(buffer.read_u16()?);
-
-        let _ = buffer.read_u16()?; // class, handel them in future versions.
+        self.class = DnsClass::from_num(buffer.read_u16()?);
 
         Ok(())
     }
@@ -26,7 +31,7 @@ impl DnsQuestion {
 
         let typenum = self.qtype.to_num();
         buffer.write_u16(typenum)?;
-        buffer.write_u16(1)?;
+        buffer.write_u16(self.class.to_num())?;
 
         Ok(())
     }
@@ -1,5 +1,18 @@
+use std::fmt;
+use std::iter::Peekable;
 use std::net::{Ipv4Addr, Ipv6Addr};
+use std::slice;
+use std::str::FromStr;
 
+use anyhow::{anyhow, bail};
+
+use super::class::DnsClass;
+use super::encoding::{
+    from_hex, parse_character_string, parse_ttl, quote_character_string, to_hex, tokenize,
+    unescape, Token,
+};
+use super::name;
+use super::svcb::{self, SvcParam};
 use super::Result;
 use super::{buffer::BytePacketBuffer, qtype::QueryType};
 
@@ -8,27 +21,38 @@ use super::{buffer::BytePacketBuffer, qtype::QueryType};
 pub enum DnsRecord {
     UNKONWN {
         domain: String,
+        class: DnsClass,
         qtype: u16,
-        data_len: u16,
+        data: Vec<u8>,
         ttl: u32,
     },
     A {
         domain: String,
+        class: DnsClass,
         addr: Ipv4Addr,
         ttl: u32,
     }, // 1
     NS {
         domain: String,
+        class: DnsClass,
         host: String,
         ttl: u32,
     },
     CNAME {
         domain: String,
+        class: DnsClass,
+        host: String,
+        ttl: u32,
+    },
+    PTR {
+        domain: String,
+        class: DnsClass,
         host: String,
         ttl: u32,
     },
     SOA {
         domain: String,
+        class: DnsClass,
         ttl: u32,
 
         mname: String,
@@ -39,31 +63,637 @@ pub enum DnsRecord {
         expire: u32,
         minimum: u32,
     },
+    HINFO {
+        domain: String,
+        class: DnsClass,
+        cpu: Vec<u8>,
+        os: Vec<u8>,
+        ttl: u32,
+    }, // 13
     MX {
         domain: String,
+        class: DnsClass,
         priority: u16,
         host: String,
         ttl: u32,
     },
+    TXT {
+        domain: String,
+        class: DnsClass,
+        data: Vec<Vec<u8>>,
+        ttl: u32,
+    }, // 16
+    RP {
+        domain: String,
+        class: DnsClass,
+        mbox: String,
+        txt: String,
+        ttl: u32,
+    }, // 17
     AAAA {
         domain: String,
+        class: DnsClass,
         addr: Ipv6Addr,
         ttl: u32,
-    },
+    }, // 28
+    LOC {
+        domain: String,
+        class: DnsClass,
+        version: u8,
+        size: u8,
+        horiz_pre: u8,
+        vert_pre: u8,
+        latitude: u32,
+        longitude: u32,
+        altitude: u32,
+        ttl: u32,
+    }, // 29
+    NAPTR {
+        domain: String,
+        class: DnsClass,
+        order: u16,
+        preference: u16,
+        flags: Vec<u8>,
+        services: Vec<u8>,
+        regexp: Vec<u8>,
+        replacement: String,
+        ttl: u32,
+    }, // 35
+    DNAME {
+        domain: String,
+        class: DnsClass,
+        host: String,
+        ttl: u32,
+    }, // 39
+    SSHFP {
+        domain: String,
+        class: DnsClass,
+        algorithm: u8,
+        fp_type: u8,
+        fingerprint: Vec<u8>,
+        ttl: u32,
+    }, // 44
+    TLSA {
+        domain: String,
+        class: DnsClass,
+        cert_usage: u8,
+        selector: u8,
+        matching_type: u8,
+        cert_data: Vec<u8>,
+        ttl: u32,
+    }, // 52
+    SVCB {
+        domain: String,
+        class: DnsClass,
+        priority: u16,
+        target: String,
+        params: Vec<SvcParam>,
+        ttl: u32,
+    }, // 64
+    HTTPS {
+        domain: String,
+        class: DnsClass,
+        priority: u16,
+        target: String,
+        params: Vec<SvcParam>,
+        ttl: u32,
+    }, // 65
+    URI {
+        domain: String,
+        class: DnsClass,
+        priority: u16,
+        weight: u16,
+        target: Vec<u8>,
+        ttl: u32,
+    }, // 256
+    CAA {
+        domain: String,
+        class: DnsClass,
+        flags: u8,
+        tag: String,
+        value: Vec<u8>,
+        ttl: u32,
+    }, // 257
 }
 
 impl DnsRecord {
+    /// Owner name of the record.
+    pub fn domain(&self) -> &str {
+        match self {
+            DnsRecord::UNKONWN { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::HINFO { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::RP { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::LOC { domain, .. }
+            | DnsRecord::NAPTR { domain, .. }
+            | DnsRecord::DNAME { domain, .. }
+            | DnsRecord::SSHFP { domain, .. }
+            | DnsRecord::TLSA { domain, .. }
+            | DnsRecord::SVCB { domain, .. }
+            | DnsRecord::HTTPS { domain, .. }
+            | DnsRecord::URI { domain, .. }
+            | DnsRecord::CAA { domain, .. } => domain,
+        }
+    }
+
+    pub fn class(&self) -> DnsClass {
+        match self {
+            DnsRecord::UNKONWN { class, .. }
+            | DnsRecord::A { class, .. }
+            | DnsRecord::NS { class, .. }
+            | DnsRecord::CNAME { class, .. }
+            | DnsRecord::PTR { class, .. }
+            | DnsRecord::SOA { class, .. }
+            | DnsRecord::HINFO { class, .. }
+            | DnsRecord::MX { class, .. }
+            | DnsRecord::TXT { class, .. }
+            | DnsRecord::RP { class, .. }
+            | DnsRecord::AAAA { class, .. }
+            | DnsRecord::LOC { class, .. }
+            | DnsRecord::NAPTR { class, .. }
+            | DnsRecord::DNAME { class, .. }
+            | DnsRecord::SSHFP { class, .. }
+            | DnsRecord::TLSA { class, .. }
+            | DnsRecord::SVCB { class, .. }
+            | DnsRecord::HTTPS { class, .. }
+            | DnsRecord::URI { class, .. }
+            | DnsRecord::CAA { class, .. } => *class,
+        }
+    }
+
+    pub fn qtype(&self) -> QueryType {
+        match self {
+            DnsRecord::UNKONWN { qtype, .. } => QueryType::from_num(*qtype),
+            DnsRecord::A { .. } => QueryType::A,
+            DnsRecord::NS { .. } => QueryType::NS,
+            DnsRecord::CNAME { .. } => QueryType::CNAME,
+            DnsRecord::PTR { .. } => QueryType::PTR,
+            DnsRecord::SOA { .. } => QueryType::SOA,
+            DnsRecord::HINFO { .. } => QueryType::HINFO,
+            DnsRecord::MX { .. } => QueryType::MX,
+            DnsRecord::TXT { .. } => QueryType::TXT,
+            DnsRecord::RP { .. } => QueryType::RP,
+            DnsRecord::AAAA { .. } => QueryType::AAAA,
+            DnsRecord::LOC { .. } => QueryType::LOC,
+            DnsRecord::NAPTR { .. } => QueryType::NAPTR,
+            DnsRecord::DNAME { .. } => QueryType::DNAME,
+            DnsRecord::SSHFP { .. } => QueryType::SSHFP,
+            DnsRecord::TLSA { .. } => QueryType::TLSA,
+            DnsRecord::SVCB { .. } => QueryType::SVCB,
+            DnsRecord::HTTPS { .. } => QueryType::HTTPS,
+            DnsRecord::URI { .. } => QueryType::URI,
+            DnsRecord::CAA { .. } => QueryType::CAA,
+        }
+    }
+
+    /// The record data alone in presentation format, as `dig +short` shows
+    /// it.
+    pub fn rdata(&self) -> Rdata<'_> {
+        Rdata(self)
+    }
+
+    /// Time to live of the record, in seconds.
+    pub fn ttl(&self) -> u32 {
+        match self {
+            DnsRecord::UNKONWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::RP { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::LOC { ttl, .. }
+            | DnsRecord::NAPTR { ttl, .. }
+            | DnsRecord::DNAME { ttl, .. }
+            | DnsRecord::SSHFP { ttl, .. }
+            | DnsRecord::TLSA { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::URI { ttl, .. }
+            | DnsRecord::CAA { ttl, .. } => *ttl,
+        }
+    }
+
+    /// Give the record a new owner, as when synthesizing it from a
+    /// wildcard.
+    pub fn set_domain(&mut self, name: String) {
+        match self {
+            DnsRecord::UNKONWN { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::HINFO { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::RP { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::LOC { domain, .. }
+            | DnsRecord::NAPTR { domain, .. }
+            | DnsRecord::DNAME { domain, .. }
+            | DnsRecord::SSHFP { domain, .. }
+            | DnsRecord::TLSA { domain, .. }
+            | DnsRecord::SVCB { domain, .. }
+            | DnsRecord::HTTPS { domain, .. }
+            | DnsRecord::URI { domain, .. }
+            | DnsRecord::CAA { domain, .. } => *domain = name,
+        }
+    }
+
+    pub fn set_ttl(&mut self, new_ttl: u32) {
+        match self {
+            DnsRecord::UNKONWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::RP { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::LOC { ttl, .. }
+            | DnsRecord::NAPTR { ttl, .. }
+            | DnsRecord::DNAME { ttl, .. }
+            | DnsRecord::SSHFP { ttl, .. }
+            | DnsRecord::TLSA { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::URI { ttl, .. }
+            | DnsRecord::CAA { ttl, .. } => *ttl = new_ttl,
+        }
+    }
+
+    /// Put the record in another class, as when an UPDATE names the
+    /// record to delete with class NONE.
+    pub fn set_class(&mut self, new_class: DnsClass) {
+        match self {
+            DnsRecord::UNKONWN { class, .. }
+            | DnsRecord::A { class, .. }
+            | DnsRecord::NS { class, .. }
+            | DnsRecord::CNAME { class, .. }
+            | DnsRecord::PTR { class, .. }
+            | DnsRecord::SOA { class, .. }
+            | DnsRecord::HINFO { class, .. }
+            | DnsRecord::MX { class, .. }
+            | DnsRecord::TXT { class, .. }
+            | DnsRecord::RP { class, .. }
+            | DnsRecord::AAAA { class, .. }
+            | DnsRecord::LOC { class, .. }
+            | DnsRecord::NAPTR { class, .. }
+            | DnsRecord::DNAME { class, .. }
+            | DnsRecord::SSHFP { class, .. }
+            | DnsRecord::TLSA { class, .. }
+            | DnsRecord::SVCB { class, .. }
+            | DnsRecord::HTTPS { class, .. }
+            | DnsRecord::URI { class, .. }
+            | DnsRecord::CAA { class, .. } => *class = new_class,
+        }
+    }
+
+    /// The CNAME a DNAME record implies for `qname` (RFC 6672, section 2.2).
+    ///
+    /// Returns `None` if this isn't a DNAME or `qname` doesn't lie strictly
+    /// below its owner, and an error if the substituted name would be longer
+    /// than 255 octets, which is to be answered with YXDOMAIN.
+    pub fn synthesize_cname(&self, qname: &str) -> Result<Option<DnsRecord>> {
+        let (owner, class, target, ttl) = match self {
+            DnsRecord::DNAME {
+                domain,
+                class,
+                host,
+                ttl,
+            } => (domain, *class, host, *ttl),
+            _ => return Ok(None),
+        };
+        if !name::is_proper_subdomain(qname, owner) {
+            return Ok(None);
+        }
+
+        let host = name::replace_suffix(qname, owner, target);
+        if name::wire_len(&host) > 255 {
+            bail!("DNAME substitution of {} yields an overlong name", qname);
+        }
+
+        Ok(Some(DnsRecord::CNAME {
+            domain: qname.to_string(),
+            class,
+            host,
+            ttl,
+        }))
+    }
+
+    /// Build a record from its rdata in presentation format, split into
+    /// tokens. Relative names in the rdata are completed with `origin`.
+    pub fn from_rdata(
+        domain: String,
+        class: DnsClass,
+        ttl: u32,
+        qtype: QueryType,
+        rdata: &[Token],
+        origin: &str,
+    ) -> Result<DnsRecord> {
+        if let Some((first, rest)) = rdata.split_first() {
+            if !first.quoted && first.text == "\\#" {
+                return Self::from_generic_rdata(domain, class, ttl, qtype, rest);
+            }
+        }
+
+        let mut fields = RdataFields {
+            tokens: rdata.iter().peekable(),
+            qtype,
+            origin,
+        };
+
+        let record = match qtype {
+            QueryType::A => DnsRecord::A {
+                domain,
+                class,
+                addr: fields.parse()?,
+                ttl,
+            },
+            QueryType::NS => DnsRecord::NS {
+                domain,
+                class,
+                host: fields.name()?,
+                ttl,
+            },
+            QueryType::CNAME => DnsRecord::CNAME {
+                domain,
+                class,
+                host: fields.name()?,
+                ttl,
+            },
+            QueryType::PTR => DnsRecord::PTR {
+                domain,
+                class,
+                host: fields.name()?,
+                ttl,
+            },
+            QueryType::SOA => DnsRecord::SOA {
+                domain,
+                class,
+                ttl,
+
+                mname: fields.name()?,
+                rname: fields.name()?,
+                serial: fields.parse()?,
+                refresh: fields.ttl()?,
+                retry: fields.ttl()?,
+                expire: fields.ttl()?,
+                minimum: fields.ttl()?,
+            },
+            QueryType::HINFO => DnsRecord::HINFO {
+                domain,
+                class,
+                cpu: fields.character_string()?,
+                os: fields.character_string()?,
+                ttl,
+            },
+            QueryType::MX => DnsRecord::MX {
+                domain,
+                class,
+                priority: fields.parse()?,
+                host: fields.name()?,
+                ttl,
+            },
+            QueryType::TXT => {
+                let mut data = vec![fields.character_string()?];
+                while fields.tokens.peek().is_some() {
+                    data.push(fields.character_string()?);
+                }
+                DnsRecord::TXT {
+                    domain,
+                    class,
+                    data,
+                    ttl,
+                }
+            }
+            QueryType::RP => DnsRecord::RP {
+                domain,
+                class,
+                mbox: fields.name()?,
+                txt: fields.name()?,
+                ttl,
+            },
+            QueryType::AAAA => DnsRecord::AAAA {
+                domain,
+                class,
+                addr: fields.parse()?,
+                ttl,
+            },
+            QueryType::LOC => {
+                let latitude = loc::parse_coordinate(&mut fields, 'N', 'S', 90)?;
+                let longitude = loc::parse_coordinate(&mut fields, 'E', 'W', 180)?;
+                let altitude = loc::parse_altitude(&fields.next()?.text)?;
+                let mut precision = |default| match fields.tokens.next() {
+                    Some(token) => loc::parse_precision(&token.text),
+                    None => Ok(default),
+                };
+                DnsRecord::LOC {
+                    domain,
+                    class,
+                    version: 0,
+                    size: precision(0x12)?,
+                    horiz_pre: precision(0x16)?,
+                    vert_pre: precision(0x13)?,
+                    latitude,
+                    longitude,
+                    altitude,
+                    ttl,
+                }
+            }
+            QueryType::NAPTR => DnsRecord::NAPTR {
+                domain,
+                class,
+                order: fields.parse()?,
+                preference: fields.parse()?,
+                flags: fields.character_string()?,
+                services: fields.character_string()?,
+                regexp: fields.character_string()?,
+                replacement: fields.name()?,
+                ttl,
+            },
+            QueryType::DNAME => DnsRecord::DNAME {
+                domain,
+                class,
+                host: fields.name()?,
+                ttl,
+            },
+            QueryType::SSHFP => DnsRecord::SSHFP {
+                domain,
+                class,
+                algorithm: fields.parse()?,
+                fp_type: fields.parse()?,
+                fingerprint: fields.hex()?,
+                ttl,
+            },
+            QueryType::TLSA => DnsRecord::TLSA {
+                domain,
+                class,
+                cert_usage: fields.parse()?,
+                selector: fields.parse()?,
+                matching_type: fields.parse()?,
+                cert_data: fields.hex()?,
+                ttl,
+            },
+            QueryType::SVCB | QueryType::HTTPS => {
+                let priority = fields.parse()?;
+                let target = fields.name()?;
+                let mut params = Vec::new();
+                for token in fields.tokens.by_ref() {
+                    params.push(token.text.parse::<SvcParam>()?);
+                }
+                params.sort_by_key(SvcParam::key);
+                svcb::validate(&params)?;
+
+                if qtype == QueryType::SVCB {
+                    DnsRecord::SVCB {
+                        domain,
+                        class,
+                        priority,
+                        target,
+                        params,
+                        ttl,
+                    }
+                } else {
+                    DnsRecord::HTTPS {
+                        domain,
+                        class,
+                        priority,
+                        target,
+                        params,
+                        ttl,
+                    }
+                }
+            }
+            QueryType::URI => DnsRecord::URI {
+                domain,
+                class,
+                priority: fields.parse()?,
+                weight: fields.parse()?,
+                target: unescape(&fields.next()?.text)?,
+                ttl,
+            },
+            QueryType::CAA => {
+                let flags = fields.parse()?;
+                let tag = fields.next()?.text.clone();
+                if tag.is_empty() || !tag.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                    bail!("CAA tag must be non-empty and alphanumeric");
+                }
+                DnsRecord::CAA {
+                    domain,
+                    class,
+                    flags,
+                    tag,
+                    value: unescape(&fields.next()?.text)?,
+                    ttl,
+                }
+            }
+            QueryType::OPT
+            | QueryType::TSIG
+            | QueryType::IXFR
+            | QueryType::AXFR
+            | QueryType::ANY
+            | QueryType::UNKNOWN(_) => {
+                bail!("cannot parse the rdata of {} records", qtype)
+            }
+        };
+
+        if let Some(extra) = fields.tokens.next() {
+            bail!("unexpected {:?} after {} rdata", extra.text, qtype);
+        }
+        Ok(record)
+    }
+
+    /// Build a record from rdata in the generic form of RFC 3597, section 5:
+    /// its length and then its bytes in hex, the tokens after the `\#`.
+    /// Records of known types are decoded as if they came off the wire.
+    fn from_generic_rdata(
+        domain: String,
+        class: DnsClass,
+        ttl: u32,
+        qtype: QueryType,
+        rdata: &[Token],
+    ) -> Result<DnsRecord> {
+        let (len, hex) = match rdata.split_first() {
+            Some(fields) => fields,
+            None => bail!("missing rdata length after \\# in {} record", qtype),
+        };
+        let len: u16 = len
+            .text
+            .parse()
+            .map_err(|_| anyhow!("invalid rdata length {:?} in {} record", len.text, qtype))?;
+        let hex: String = hex.iter().map(|token| token.text.as_str()).collect();
+        let data = from_hex(&hex)?;
+        if data.len() != len as usize {
+            bail!(
+                "{} record has {} bytes of rdata, not {}",
+                qtype,
+                data.len(),
+                len
+            );
+        }
+
+        match qtype {
+            QueryType::UNKNOWN(num) => Ok(DnsRecord::UNKONWN {
+                domain,
+                class,
+                qtype: num,
+                data,
+                ttl,
+            }),
+            QueryType::OPT
+            | QueryType::TSIG
+            | QueryType::IXFR
+            | QueryType::AXFR
+            | QueryType::ANY => bail!("cannot parse the rdata of {} records", qtype),
+            _ => {
+                let mut buffer = BytePacketBuffer::new();
+                let pos = Self::write_preamble(&mut buffer, &domain, qtype, class, ttl)?;
+                buffer.write_bytes(&data)?;
+                Self::write_rdata_len(&mut buffer, pos)?;
+                let end = buffer.pos();
+
+                buffer.seek(0)?;
+                let record = Self::read(&mut buffer)?;
+                if buffer.pos() != end {
+                    bail!(
+                        "{} record has {} bytes of rdata too many",
+                        qtype,
+                        end - buffer.pos()
+                    );
+                }
+                Ok(record)
+            }
+        }
+    }
+
     pub fn read(buffer: &mut BytePacketBuffer) -> Result<DnsRecord> {
         let domain = buffer.read_qname()?;
 
         let qtype_num = buffer.read_u16()?;
         let qtype = QueryType::from_num(qtype_num);
 
-        let _ = buffer.read_u16()?;
+        let class = DnsClass::from_num(buffer.read_u16()?);
         let ttl = buffer.read_u32()?;
         let data_len = buffer.read_u16()?;
+        let data_end = buffer.pos() + data_len as usize;
 
-        match qtype {
+        let record: Result<DnsRecord> = match qtype {
             QueryType::A => {
                 let raw_addr = buffer.read_u32()?;
                 let addr = Ipv4Addr::new(
@@ -73,12 +703,18 @@ impl DnsRecord {
                     (raw_addr & 0xff) as u8,
                 );
 
-                Ok(DnsRecord::A { domain, addr, ttl })
+                Ok(DnsRecord::A {
+                    domain,
+                    class,
+                    addr,
+                    ttl,
+                })
             }
             QueryType::NS => {
                 let ns = buffer.read_qname()?;
                 Ok(DnsRecord::NS {
                     domain,
+                    class,
                     host: ns,
                     ttl,
                 })
@@ -87,10 +723,20 @@ impl DnsRecord {
                 let cname = buffer.read_qname()?;
                 Ok(DnsRecord::CNAME {
                     domain,
+                    class,
                     host: cname,
                     ttl,
                 })
             }
+            QueryType::PTR => {
+                let ptr = buffer.read_qname()?;
+                Ok(DnsRecord::PTR {
+                    domain,
+                    class,
+                    host: ptr,
+                    ttl,
+                })
+            }
             QueryType::SOA => {
                 let mname = buffer.read_qname()?;
                 let rname = buffer.read_qname()?;
@@ -101,6 +747,7 @@ impl DnsRecord {
                 let minimum = buffer.read_u32()?;
                 Ok(DnsRecord::SOA {
                     domain,
+                    class,
                     ttl,
 
                     mname,
@@ -112,21 +759,59 @@ impl DnsRecord {
                     minimum,
                 })
             }
+            QueryType::HINFO => {
+                let cpu = buffer.read_character_string()?;
+                let os = buffer.read_character_string()?;
+                Ok(DnsRecord::HINFO {
+                    domain,
+                    class,
+                    cpu,
+                    os,
+                    ttl,
+                })
+            }
             QueryType::MX => {
                 let priority = buffer.read_u16()?;
                 let mx = buffer.read_qname()?;
 
                 Ok(DnsRecord::MX {
                     domain,
+                    class,
                     priority,
                     host: mx,
                     ttl,
                 })
             }
+            QueryType::TXT => {
+                let mut data = Vec::new();
+                while buffer.pos() < data_end {
+                    data.push(buffer.read_character_string()?);
+                }
+                if data.is_empty() || buffer.pos() != data_end {
+                    bail!("malformed TXT record of {} bytes", data_len);
+                }
+                Ok(DnsRecord::TXT {
+                    domain,
+                    class,
+                    data,
+                    ttl,
+                })
+            }
+            QueryType::RP => {
+                let mbox = buffer.read_qname()?;
+                let txt = buffer.read_qname()?;
+                Ok(DnsRecord::RP {
+                    domain,
+                    class,
+                    mbox,
+                    txt,
+                    ttl,
+                })
+            }
             QueryType::AAAA => {
                 let mut raw_addr = [0; 8];
-                for i in 0..8 {
-                    raw_addr[i] = buffer.read_u16()?;
+                for segment in raw_addr.iter_mut() {
+                    *segment = buffer.read_u16()?;
                 }
                 let addr = Ipv6Addr::new(
                     raw_addr[0],
@@ -138,19 +823,203 @@ impl DnsRecord {
                     raw_addr[6],
                     raw_addr[7],
                 );
-                Ok(DnsRecord::AAAA { domain, addr, ttl })
+                Ok(DnsRecord::AAAA {
+                    domain,
+                    class,
+                    addr,
+                    ttl,
+                })
+            }
+            QueryType::LOC => {
+                let version = buffer.read()?;
+                if version != 0 {
+                    bail!("unsupported LOC record version {}", version);
+                }
+                if data_len != 16 {
+                    bail!("LOC record must be 16 bytes, got {}", data_len);
+                }
+                let size = buffer.read()?;
+                let horiz_pre = buffer.read()?;
+                let vert_pre = buffer.read()?;
+                let latitude = buffer.read_u32()?;
+                let longitude = buffer.read_u32()?;
+                let altitude = buffer.read_u32()?;
+                Ok(DnsRecord::LOC {
+                    domain,
+                    class,
+                    version,
+                    size,
+                    horiz_pre,
+                    vert_pre,
+                    latitude,
+                    longitude,
+                    altitude,
+                    ttl,
+                })
+            }
+            QueryType::NAPTR => {
+                let order = buffer.read_u16()?;
+                let preference = buffer.read_u16()?;
+                let flags = buffer.read_character_string()?;
+                let services = buffer.read_character_string()?;
+                let regexp = buffer.read_character_string()?;
+                let replacement = buffer.read_qname()?;
+                Ok(DnsRecord::NAPTR {
+                    domain,
+                    class,
+                    order,
+                    preference,
+                    flags,
+                    services,
+                    regexp,
+                    replacement,
+                    ttl,
+                })
+            }
+            QueryType::DNAME => {
+                let dname = buffer.read_qname()?;
+                Ok(DnsRecord::DNAME {
+                    domain,
+                    class,
+                    host: dname,
+                    ttl,
+                })
+            }
+            QueryType::SSHFP => {
+                if data_len < 2 {
+                    bail!("SSHFP record of {} bytes is too short", data_len);
+                }
+                let algorithm = buffer.read()?;
+                let fp_type = buffer.read()?;
+                let fingerprint = buffer.read_bytes(data_len as usize - 2)?;
+
+                Ok(DnsRecord::SSHFP {
+                    domain,
+                    class,
+                    algorithm,
+                    fp_type,
+                    fingerprint,
+                    ttl,
+                })
+            }
+            QueryType::TLSA => {
+                if data_len < 3 {
+                    bail!("TLSA record of {} bytes is too short", data_len);
+                }
+                let cert_usage = buffer.read()?;
+                let selector = buffer.read()?;
+                let matching_type = buffer.read()?;
+                let cert_data = buffer.read_bytes(data_len as usize - 3)?;
+
+                Ok(DnsRecord::TLSA {
+                    domain,
+                    class,
+                    cert_usage,
+                    selector,
+                    matching_type,
+                    cert_data,
+                    ttl,
+                })
+            }
+            QueryType::SVCB | QueryType::HTTPS => {
+                let priority = buffer.read_u16()?;
+                let target = buffer.read_qname()?;
+                let mut params = Vec::new();
+                while buffer.pos() < data_end {
+                    params.push(SvcParam::read(buffer)?);
+                }
+                if buffer.pos() != data_end {
+                    bail!("SvcParams overrun the record's rdata");
+                }
+                svcb::validate(&params)?;
+
+                if qtype == QueryType::SVCB {
+                    Ok(DnsRecord::SVCB {
+                        domain,
+                        class,
+                        priority,
+                        target,
+                        params,
+                        ttl,
+                    })
+                } else {
+                    Ok(DnsRecord::HTTPS {
+                        domain,
+                        class,
+                        priority,
+                        target,
+                        params,
+                        ttl,
+                    })
+                }
+            }
+            QueryType::URI => {
+                if data_len < 4 {
+                    bail!("URI record of {} bytes is too short", data_len);
+                }
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+                let target = buffer.read_bytes(data_len as usize - 4)?;
+                Ok(DnsRecord::URI {
+                    domain,
+                    class,
+                    priority,
+                    weight,
+                    target,
+                    ttl,
+                })
+            }
+            QueryType::CAA => {
+                let flags = buffer.read()?;
+                let tag = buffer.read_character_string()?;
+                if tag.is_empty() || !tag.iter().all(u8::is_ascii_alphanumeric) {
+                    bail!("CAA tag must be non-empty and alphanumeric");
+                }
+                let value_len = (data_len as usize)
+                    .checked_sub(2 + tag.len())
+                    .ok_or_else(|| anyhow!("CAA record of {} bytes is too short", data_len))?;
+                let value = buffer.read_bytes(value_len)?;
+
+                Ok(DnsRecord::CAA {
+                    domain,
+                    class,
+                    flags,
+                    tag: String::from_utf8_lossy(&tag).into_owned(),
+                    value,
+                    ttl,
+                })
             }
-            QueryType::UNKNOWN(_) => {
-                buffer.step(data_len as usize)?;
+            // OPT records are picked out by `DnsPacket` before getting here;
+            // one found elsewhere is kept as opaque data.
+            QueryType::OPT
+            | QueryType::TSIG
+            | QueryType::IXFR
+            | QueryType::AXFR
+            | QueryType::ANY
+            | QueryType::UNKNOWN(_) => {
+                let data = buffer.read_bytes(data_len as usize)?;
 
                 Ok(DnsRecord::UNKONWN {
                     domain,
+                    class,
                     qtype: qtype_num,
-                    data_len,
+                    data,
                     ttl,
                 })
             }
+        };
+        let record = record?;
+
+        // a wrong length would have the records after this one read from
+        // the wrong place
+        if buffer.pos() != data_end {
+            bail!(
+                "{} rdata does not match its length of {} bytes",
+                qtype,
+                data_len
+            );
         }
+        Ok(record)
     }
 
     pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<usize> {
@@ -159,58 +1028,47 @@ impl DnsRecord {
         match *self {
             DnsRecord::A {
                 ref domain,
+                class,
                 ref addr,
                 ttl,
             } => {
-                // write head
-                buffer.write_qname(domain)?;
-                buffer.write_u16(QueryType::A.to_num())?;
-                buffer.write_u16(1)?;
-                buffer.write_u32(ttl)?;
-                buffer.write_u16(4)?;
-
-                let octets = addr.octets();
-                buffer.write_u8(octets[0])?;
-                buffer.write_u8(octets[1])?;
-                buffer.write_u8(octets[2])?;
-                buffer.write_u8(octets[3])?;
+                let pos = Self::write_preamble(buffer, domain, QueryType::A, class, ttl)?;
+                buffer.write_bytes(&addr.octets())?;
+                Self::write_rdata_len(buffer, pos)?;
             }
             DnsRecord::NS {
                 ref domain,
+                class,
                 ref host,
                 ttl,
             } => {
-                buffer.write_qname(domain)?;
-                buffer.write_u16(QueryType::NS.to_num())?;
-                buffer.write_u16(1)?;
-                buffer.write_u32(ttl)?;
-                let pos = buffer.pos();
-                buffer.write_u16(0)?;
+                let pos = Self::write_preamble(buffer, domain, QueryType::NS, class, ttl)?;
                 buffer.write_qname(host)?;
-
-                let size = buffer.pos() - (pos + 2);
-                buffer.set_u16(pos, size as u16)?;
+                Self::write_rdata_len(buffer, pos)?;
             }
             DnsRecord::CNAME {
                 ref domain,
+                class,
                 ref host,
                 ttl,
             } => {
-                buffer.write_qname(domain)?;
-                buffer.write_u16(QueryType::CNAME.to_num())?;
-                buffer.write_u16(1)?;
-                buffer.write_u32(ttl)?;
-
-                let pos = buffer.pos();
-                buffer.write_u16(0)?;
-
+                let pos = Self::write_preamble(buffer, domain, QueryType::CNAME, class, ttl)?;
                 buffer.write_qname(host)?;
-
-                let size = buffer.pos() - (pos + 2);
-                buffer.set_u16(pos, size as u16)?;
+                Self::write_rdata_len(buffer, pos)?;
+            }
+            DnsRecord::PTR {
+                ref domain,
+                class,
+                ref host,
+                ttl,
+            } => {
+                let pos = Self::write_preamble(buffer, domain, QueryType::PTR, class, ttl)?;
+                buffer.write_qname(host)?;
+                Self::write_rdata_len(buffer, pos)?;
             }
             DnsRecord::SOA {
                 ref domain,
+                class,
                 ttl,
 
                 ref mname,
@@ -221,13 +1079,7 @@ impl DnsRecord {
                 expire,
                 minimum,
             } => {
-                buffer.write_qname(domain)?;
-                buffer.write_u16(QueryType::SOA.to_num())?;
-                buffer.write_u16(1)?;
-                buffer.write_u32(ttl)?;
-
-                let pos = buffer.pos();
-                buffer.write_u16(0)?;
+                let pos = Self::write_preamble(buffer, domain, QueryType::SOA, class, ttl)?;
 
                 buffer.write_qname(mname)?;
                 buffer.write_qname(rname)?;
@@ -237,50 +1089,744 @@ impl DnsRecord {
                 buffer.write_u32(expire)?;
                 buffer.write_u32(minimum)?;
 
-                let size = buffer.pos() - (pos + 2);
-                buffer.set_u16(pos, size as u16)?;
+                Self::write_rdata_len(buffer, pos)?;
+            }
+            DnsRecord::HINFO {
+                ref domain,
+                class,
+                ref cpu,
+                ref os,
+                ttl,
+            } => {
+                let pos = Self::write_preamble(buffer, domain, QueryType::HINFO, class, ttl)?;
+                buffer.write_character_string(cpu)?;
+                buffer.write_character_string(os)?;
+                Self::write_rdata_len(buffer, pos)?;
             }
             DnsRecord::MX {
                 ref domain,
+                class,
                 priority,
                 ref host,
                 ttl,
             } => {
-                buffer.write_qname(domain)?;
-                buffer.write_u16(QueryType::MX.to_num())?;
-                buffer.write_u16(1)?;
-                buffer.write_u32(ttl)?;
-
-                let pos = buffer.pos();
-                buffer.write_u16(0)?;
-
+                let pos = Self::write_preamble(buffer, domain, QueryType::MX, class, ttl)?;
                 buffer.write_u16(priority)?;
                 buffer.write_qname(host)?;
-
-                let size = buffer.pos() - (pos + 2);
-                buffer.set_u16(pos, size as u16)?;
+                Self::write_rdata_len(buffer, pos)?;
+            }
+            DnsRecord::TXT {
+                ref domain,
+                class,
+                ref data,
+                ttl,
+            } => {
+                let pos = Self::write_preamble(buffer, domain, QueryType::TXT, class, ttl)?;
+                for string in data {
+                    buffer.write_character_string(string)?;
+                }
+                Self::write_rdata_len(buffer, pos)?;
+            }
+            DnsRecord::RP {
+                ref domain,
+                class,
+                ref mbox,
+                ref txt,
+                ttl,
+            } => {
+                let pos = Self::write_preamble(buffer, domain, QueryType::RP, class, ttl)?;
+                buffer.write_qname(mbox)?;
+                buffer.write_qname(txt)?;
+                Self::write_rdata_len(buffer, pos)?;
             }
             DnsRecord::AAAA {
                 ref domain,
+                class,
                 ref addr,
                 ttl,
             } => {
-                buffer.write_qname(domain)?;
-                buffer.write_u16(QueryType::AAAA.to_num())?;
-                buffer.write_u16(1)?;
-                buffer.write_u32(ttl)?;
-                buffer.write_u16(16)?;
-
+                let pos = Self::write_preamble(buffer, domain, QueryType::AAAA, class, ttl)?;
                 for octet in &addr.segments() {
                     buffer.write_u16(*octet)?;
                 }
+                Self::write_rdata_len(buffer, pos)?;
+            }
+            DnsRecord::LOC {
+                ref domain,
+                class,
+                version,
+                size,
+                horiz_pre,
+                vert_pre,
+                latitude,
+                longitude,
+                altitude,
+                ttl,
+            } => {
+                let pos = Self::write_preamble(buffer, domain, QueryType::LOC, class, ttl)?;
+                buffer.write_u8(version)?;
+                buffer.write_u8(size)?;
+                buffer.write_u8(horiz_pre)?;
+                buffer.write_u8(vert_pre)?;
+                buffer.write_u32(latitude)?;
+                buffer.write_u32(longitude)?;
+                buffer.write_u32(altitude)?;
+                Self::write_rdata_len(buffer, pos)?;
+            }
+            DnsRecord::NAPTR {
+                ref domain,
+                class,
+                order,
+                preference,
+                ref flags,
+                ref services,
+                ref regexp,
+                ref replacement,
+                ttl,
+            } => {
+                let pos = Self::write_preamble(buffer, domain, QueryType::NAPTR, class, ttl)?;
+                buffer.write_u16(order)?;
+                buffer.write_u16(preference)?;
+                buffer.write_character_string(flags)?;
+                buffer.write_character_string(services)?;
+                buffer.write_character_string(regexp)?;
+                buffer.write_qname(replacement)?;
+                Self::write_rdata_len(buffer, pos)?;
+            }
+            DnsRecord::DNAME {
+                ref domain,
+                class,
+                ref host,
+                ttl,
+            } => {
+                let pos = Self::write_preamble(buffer, domain, QueryType::DNAME, class, ttl)?;
+                buffer.write_qname(host)?;
+                Self::write_rdata_len(buffer, pos)?;
+            }
+            DnsRecord::SSHFP {
+                ref domain,
+                class,
+                algorithm,
+                fp_type,
+                ref fingerprint,
+                ttl,
+            } => {
+                let pos = Self::write_preamble(buffer, domain, QueryType::SSHFP, class, ttl)?;
+                buffer.write_u8(algorithm)?;
+                buffer.write_u8(fp_type)?;
+                buffer.write_bytes(fingerprint)?;
+                Self::write_rdata_len(buffer, pos)?;
+            }
+            DnsRecord::TLSA {
+                ref domain,
+                class,
+                cert_usage,
+                selector,
+                matching_type,
+                ref cert_data,
+                ttl,
+            } => {
+                let pos = Self::write_preamble(buffer, domain, QueryType::TLSA, class, ttl)?;
+                buffer.write_u8(cert_usage)?;
+                buffer.write_u8(selector)?;
+                buffer.write_u8(matching_type)?;
+                buffer.write_bytes(cert_data)?;
+                Self::write_rdata_len(buffer, pos)?;
+            }
+            DnsRecord::SVCB {
+                ref domain,
+                class,
+                priority,
+                ref target,
+                ref params,
+                ttl,
+            } => {
+                let pos = Self::write_preamble(buffer, domain, QueryType::SVCB, class, ttl)?;
+                Self::write_svcb_rdata(buffer, priority, target, params)?;
+                Self::write_rdata_len(buffer, pos)?;
+            }
+            DnsRecord::HTTPS {
+                ref domain,
+                class,
+                priority,
+                ref target,
+                ref params,
+                ttl,
+            } => {
+                let pos = Self::write_preamble(buffer, domain, QueryType::HTTPS, class, ttl)?;
+                Self::write_svcb_rdata(buffer, priority, target, params)?;
+                Self::write_rdata_len(buffer, pos)?;
+            }
+            DnsRecord::URI {
+                ref domain,
+                class,
+                priority,
+                weight,
+                ref target,
+                ttl,
+            } => {
+                let pos = Self::write_preamble(buffer, domain, QueryType::URI, class, ttl)?;
+                buffer.write_u16(priority)?;
+                buffer.write_u16(weight)?;
+                buffer.write_bytes(target)?;
+                Self::write_rdata_len(buffer, pos)?;
+            }
+            DnsRecord::CAA {
+                ref domain,
+                class,
+                flags,
+                ref tag,
+                ref value,
+                ttl,
+            } => {
+                let pos = Self::write_preamble(buffer, domain, QueryType::CAA, class, ttl)?;
+                buffer.write_u8(flags)?;
+                buffer.write_character_string(tag.as_bytes())?;
+                buffer.write_bytes(value)?;
+                Self::write_rdata_len(buffer, pos)?;
             }
 
-            DnsRecord::UNKONWN { .. } => {
-                println!("Skipping record: {:?}", self);
+            DnsRecord::UNKONWN {
+                ref domain,
+                class,
+                qtype,
+                ref data,
+                ttl,
+            } => {
+                let qtype = QueryType::from_num(qtype);
+                let pos = Self::write_preamble(buffer, domain, qtype, class, ttl)?;
+                buffer.write_bytes(data)?;
+                Self::write_rdata_len(buffer, pos)?;
             }
         }
 
         Ok(buffer.pos() - start_pos)
     }
+
+    /// Write owner name, type, class and TTL, followed by a placeholder for
+    /// the rdata length. Returns the position of that placeholder.
+    fn write_preamble(
+        buffer: &mut BytePacketBuffer,
+        domain: &str,
+        qtype: QueryType,
+        class: DnsClass,
+        ttl: u32,
+    ) -> Result<usize> {
+        buffer.write_qname(domain)?;
+        buffer.write_u16(qtype.to_num())?;
+        buffer.write_u16(class.to_num())?;
+        buffer.write_u32(ttl)?;
+
+        let pos = buffer.pos();
+        buffer.write_u16(0)?;
+        Ok(pos)
+    }
+
+    /// SVCB and HTTPS share their rdata layout. The target name is never
+    /// compressed.
+    fn write_svcb_rdata(
+        buffer: &mut BytePacketBuffer,
+        priority: u16,
+        target: &str,
+        params: &[SvcParam],
+    ) -> Result<()> {
+        svcb::validate(params)?;
+
+        buffer.write_u16(priority)?;
+        buffer.write_qname(target)?;
+        for param in params {
+            param.write(buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Fill in the rdata length placeholder left by `write_preamble`.
+    fn write_rdata_len(buffer: &mut BytePacketBuffer, pos: usize) -> Result<()> {
+        let size = buffer.pos() - (pos + 2);
+        buffer.set_u16(pos, size as u16)
+    }
+}
+
+impl fmt::Display for DnsRecord {
+    /// Presentation format, as it would appear in a master file:
+    /// `<owner> <ttl> <class> <type> <rdata>`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            name::or_root(self.domain()),
+            self.ttl(),
+            self.class(),
+            self.qtype(),
+            self.rdata()
+        )
+    }
+}
+
+/// The rdata of a record in presentation format, see [`DnsRecord::rdata`].
+pub struct Rdata<'a>(&'a DnsRecord);
+
+impl fmt::Display for Rdata<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            // the generic form of RFC 3597, section 5
+            DnsRecord::UNKONWN { data, .. } if data.is_empty() => write!(f, "\\# 0"),
+            DnsRecord::UNKONWN { data, .. } => write!(f, "\\# {} {}", data.len(), to_hex(data)),
+            DnsRecord::A { addr, .. } => write!(f, "{}", addr),
+            DnsRecord::NS { host, .. } => write!(f, "{}", name::or_root(host)),
+            DnsRecord::CNAME { host, .. } => write!(f, "{}", name::or_root(host)),
+            DnsRecord::PTR { host, .. } => write!(f, "{}", name::or_root(host)),
+            DnsRecord::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                name::or_root(mname),
+                name::or_root(rname),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum
+            ),
+            DnsRecord::HINFO { cpu, os, .. } => write!(
+                f,
+                "{} {}",
+                quote_character_string(cpu),
+                quote_character_string(os)
+            ),
+            DnsRecord::MX { priority, host, .. } => {
+                write!(f, "{} {}", priority, name::or_root(host))
+            }
+            DnsRecord::TXT { data, .. } => {
+                let strings: Vec<_> = data.iter().map(|s| quote_character_string(s)).collect();
+                write!(f, "{}", strings.join(" "))
+            }
+            DnsRecord::RP { mbox, txt, .. } => {
+                write!(f, "{} {}", name::or_root(mbox), name::or_root(txt))
+            }
+            DnsRecord::AAAA { addr, .. } => write!(f, "{}", addr),
+            DnsRecord::LOC {
+                size,
+                horiz_pre,
+                vert_pre,
+                latitude,
+                longitude,
+                altitude,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {} {} {}",
+                loc::coordinate(*latitude, 'N', 'S'),
+                loc::coordinate(*longitude, 'E', 'W'),
+                loc::altitude(*altitude),
+                loc::precision(*size),
+                loc::precision(*horiz_pre),
+                loc::precision(*vert_pre)
+            ),
+            DnsRecord::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {} {} {}",
+                order,
+                preference,
+                quote_character_string(flags),
+                quote_character_string(services),
+                quote_character_string(regexp),
+                name::or_root(replacement)
+            ),
+            DnsRecord::DNAME { host, .. } => write!(f, "{}", name::or_root(host)),
+            DnsRecord::SSHFP {
+                algorithm,
+                fp_type,
+                fingerprint,
+                ..
+            } => write!(f, "{} {} {}", algorithm, fp_type, to_hex(fingerprint)),
+            DnsRecord::TLSA {
+                cert_usage,
+                selector,
+                matching_type,
+                cert_data,
+                ..
+            } => write!(
+                f,
+                "{} {} {} {}",
+                cert_usage,
+                selector,
+                matching_type,
+                to_hex(cert_data)
+            ),
+            DnsRecord::SVCB {
+                priority,
+                target,
+                params,
+                ..
+            }
+            | DnsRecord::HTTPS {
+                priority,
+                target,
+                params,
+                ..
+            } => fmt_svcb_rdata(f, *priority, target, params),
+            DnsRecord::URI {
+                priority,
+                weight,
+                target,
+                ..
+            } => write!(
+                f,
+                "{} {} {}",
+                priority,
+                weight,
+                quote_character_string(target)
+            ),
+            DnsRecord::CAA {
+                flags, tag, value, ..
+            } => write!(f, "{} {} {}", flags, tag, quote_character_string(value)),
+        }
+    }
+}
+
+impl FromStr for DnsRecord {
+    type Err = anyhow::Error;
+
+    /// Parse a record as `Display` writes it: `<owner> <ttl> <class> <type>
+    /// <rdata>`. Names without a trailing dot are taken as fully qualified.
+    fn from_str(s: &str) -> Result<DnsRecord> {
+        let tokens = tokenize(s)?;
+        if tokens.len() < 4 {
+            bail!("expected <owner> <ttl> <class> <type> <rdata>, got {:?}", s);
+        }
+
+        let domain = name::from_text(&tokens[0].text, "")?;
+        let ttl = parse_ttl(&tokens[1].text)?;
+        let class = tokens[2].text.parse()?;
+        let qtype = tokens[3].text.parse()?;
+        DnsRecord::from_rdata(domain, class, ttl, qtype, &tokens[4..], "")
+    }
+}
+
+/// The rdata tokens of one record, taken field by field.
+struct RdataFields<'a> {
+    tokens: Peekable<slice::Iter<'a, Token>>,
+    qtype: QueryType,
+    origin: &'a str,
+}
+
+impl<'a> RdataFields<'a> {
+    fn next(&mut self) -> Result<&'a Token> {
+        self.tokens
+            .next()
+            .ok_or_else(|| anyhow!("missing field in {} rdata", self.qtype))
+    }
+
+    fn parse<T: FromStr>(&mut self) -> Result<T> {
+        let text = &self.next()?.text;
+        text.parse()
+            .map_err(|_| anyhow!("invalid {:?} in {} rdata", text, self.qtype))
+    }
+
+    fn ttl(&mut self) -> Result<u32> {
+        parse_ttl(&self.next()?.text)
+    }
+
+    fn name(&mut self) -> Result<String> {
+        name::from_text(&self.next()?.text, self.origin)
+    }
+
+    fn character_string(&mut self) -> Result<Vec<u8>> {
+        parse_character_string(self.next()?)
+    }
+
+    /// Hexadecimal data, which may be split over the remaining fields.
+    fn hex(&mut self) -> Result<Vec<u8>> {
+        let mut text = self.next()?.text.clone();
+        for token in self.tokens.by_ref() {
+            text.push_str(&token.text);
+        }
+        from_hex(&text)
+    }
+}
+
+fn fmt_svcb_rdata(
+    f: &mut fmt::Formatter<'_>,
+    priority: u16,
+    target: &str,
+    params: &[SvcParam],
+) -> fmt::Result {
+    write!(f, "{} {}", priority, name::or_root(target))?;
+    for param in params {
+        write!(f, " {}", param)?;
+    }
+    Ok(())
+}
+
+/// Presentation helpers for the fixed-point fields of LOC records
+/// (RFC 1876, section 3).
+mod loc {
+    use anyhow::{anyhow, bail, Result};
+
+    use super::RdataFields;
+
+    const EQUATOR: i64 = 1 << 31;
+    const ALTITUDE_BASE: i64 = 10_000_000;
+
+    /// Format centimetres as metres, dropping the fraction when it is zero.
+    fn metres(cm: i64) -> String {
+        let sign = if cm < 0 { "-" } else { "" };
+        let cm = cm.abs();
+        if cm % 100 == 0 {
+            format!("{}{}m", sign, cm / 100)
+        } else {
+            format!("{}{}.{:02}m", sign, cm / 100, cm % 100)
+        }
+    }
+
+    /// Latitude or longitude, stored as thousandths of an arc second offset
+    /// from 2^31.
+    pub fn coordinate(raw: u32, positive: char, negative: char) -> String {
+        let value = raw as i64 - EQUATOR;
+        let hemisphere = if value < 0 { negative } else { positive };
+        let value = value.abs();
+        format!(
+            "{} {} {}.{:03} {}",
+            value / 3_600_000,
+            (value / 60_000) % 60,
+            (value / 1000) % 60,
+            value % 1000,
+            hemisphere
+        )
+    }
+
+    /// Altitude, stored in centimetres above a base 100,000 m below the
+    /// WGS 84 reference spheroid.
+    pub fn altitude(raw: u32) -> String {
+        metres(raw as i64 - ALTITUDE_BASE)
+    }
+
+    /// Size and precision, stored as a base-10 mantissa and exponent of
+    /// centimetres.
+    pub fn precision(raw: u8) -> String {
+        let mantissa = (raw >> 4) as i64;
+        let exponent = (raw & 0x0f) as u32;
+        metres(mantissa * 10i64.pow(exponent.min(9)))
+    }
+
+    /// Parse `d [m [s.sss]] H`, the inverse of [`coordinate`].
+    pub fn parse_coordinate(
+        fields: &mut RdataFields,
+        positive: char,
+        negative: char,
+        max_degrees: i64,
+    ) -> Result<u32> {
+        let degrees: i64 = fields.parse()?;
+        let mut minutes = 0;
+        let mut millis = 0;
+        let mut hemisphere = fields.next()?.text.as_str();
+        if !is_hemisphere(hemisphere, positive, negative) {
+            minutes = hemisphere
+                .parse::<i64>()
+                .ok()
+                .filter(|m| (0..60).contains(m))
+                .ok_or_else(|| anyhow!("invalid LOC minutes {:?}", hemisphere))?;
+            hemisphere = fields.next()?.text.as_str();
+        }
+        if !is_hemisphere(hemisphere, positive, negative) {
+            millis = parse_fixed(hemisphere, 3)
+                .filter(|s| (0..60_000).contains(s))
+                .ok_or_else(|| anyhow!("invalid LOC seconds {:?}", hemisphere))?;
+            hemisphere = fields.next()?.text.as_str();
+        }
+        if !is_hemisphere(hemisphere, positive, negative) {
+            bail!(
+                "expected {} or {} in LOC, got {:?}",
+                positive,
+                negative,
+                hemisphere
+            );
+        }
+
+        let value = (degrees * 60 + minutes) * 60_000 + millis;
+        if !(0..=max_degrees * 3_600_000).contains(&value) {
+            bail!("LOC coordinate is out of range");
+        }
+        let value = if hemisphere.eq_ignore_ascii_case(&negative.to_string()) {
+            -value
+        } else {
+            value
+        };
+        Ok((EQUATOR + value) as u32)
+    }
+
+    fn is_hemisphere(text: &str, positive: char, negative: char) -> bool {
+        text.eq_ignore_ascii_case(&positive.to_string())
+            || text.eq_ignore_ascii_case(&negative.to_string())
+    }
+
+    /// Parse a decimal with at most `places` digits after the point, as an
+    /// integer count of its smallest unit.
+    fn parse_fixed(text: &str, places: usize) -> Option<i64> {
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(text) => (true, text),
+            None => (false, text),
+        };
+        let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+        if whole.is_empty()
+            || fraction.len() > places
+            || !whole
+                .bytes()
+                .chain(fraction.bytes())
+                .all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+        let value = whole.parse::<i64>().ok()? * 10i64.pow(places as u32)
+            + format!("{:0<1$}", fraction, places).parse::<i64>().ok()?;
+        Some(if negative { -value } else { value })
+    }
+
+    /// Parse metres, with an optional `m` suffix, into centimetres.
+    fn parse_metres(text: &str) -> Result<i64> {
+        parse_fixed(text.strip_suffix(['m', 'M']).unwrap_or(text), 2)
+            .ok_or_else(|| anyhow!("invalid LOC distance {:?}", text))
+    }
+
+    /// The inverse of [`altitude`].
+    pub fn parse_altitude(text: &str) -> Result<u32> {
+        u32::try_from(parse_metres(text)? + ALTITUDE_BASE)
+            .map_err(|_| anyhow!("LOC altitude {:?} is out of range", text))
+    }
+
+    /// The inverse of [`precision`], rounding down to one significant
+    /// digit.
+    pub fn parse_precision(text: &str) -> Result<u8> {
+        let mut mantissa = parse_metres(text)?;
+        if mantissa < 0 {
+            bail!("LOC size and precision cannot be negative");
+        }
+        let mut exponent = 0;
+        while mantissa > 9 {
+            if exponent == 9 {
+                bail!("LOC size or precision {:?} is too large", text);
+            }
+            mantissa /= 10;
+            exponent += 1;
+        }
+        Ok(((mantissa as u8) << 4) | exponent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A record of every type, as `Display` writes it.
+    const RECORDS: &[&str] = &[
+        "a.example. 300 IN A 192.0.2.1",
+        "example. 3600 IN NS ns.example.",
+        "www.example. 300 IN CNAME a.example.",
+        "1.2.0.192.in-addr.arpa. 300 IN PTR a.example.",
+        "example. 3600 IN SOA ns.example. hostmaster.example. 2024010101 3600 600 86400 300",
+        "a.example. 300 IN HINFO \"amd64\" \"Linux 6\"",
+        "example. 300 IN MX 10 mail.example.",
+        "example. 300 IN TXT \"v=spf1 -all\" \"say \\\"hi\\\"\\\\\"",
+        "example. 300 IN RP admin.example. info.example.",
+        "a.example. 300 IN AAAA 2001:db8::1",
+        "a.example. 300 IN LOC 52 22 23.000 N 4 53 32.000 E -2m 1m 10000m 10m",
+        "example. 300 IN NAPTR 100 10 \"S\" \"SIP+D2U\" \"\" _sip._udp.example.",
+        "old.example. 300 IN DNAME new.example.",
+        "a.example. 300 IN SSHFP 4 2 0123456789ABCDEF",
+        "_443._tcp.example. 300 IN TLSA 3 1 1 0123456789ABCDEF",
+        "_dns.example. 300 IN SVCB 1 dns.example. alpn=\"dot\" port=853",
+        "example. 300 IN HTTPS 1 . alpn=\"h2,h3\" ipv4hint=192.0.2.1",
+        "_http._tcp.example. 300 IN URI 10 1 \"https://www.example/\"",
+        "example. 300 IN CAA 0 issue \"ca.example\"",
+        "a.example. 300 IN TYPE65280 \\# 3 ABCDEF",
+        "a.example. 300 IN TYPE65281 \\# 0",
+        "version.bind. 0 CH TXT \"tiny-dns\"",
+    ];
+
+    #[test]
+    fn prints_what_it_parses() {
+        for text in RECORDS {
+            let rec: DnsRecord = text.parse().unwrap();
+            assert_eq!(rec.to_string(), *text);
+        }
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        for text in RECORDS {
+            let rec: DnsRecord = text.parse().unwrap();
+            let mut buffer = BytePacketBuffer::new();
+            rec.write(&mut buffer).unwrap();
+            let end = buffer.pos();
+
+            buffer.seek(0).unwrap();
+            assert_eq!(DnsRecord::read(&mut buffer).unwrap(), rec, "{}", text);
+            assert_eq!(buffer.pos(), end, "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_rdata() {
+        let malformed = [
+            "a.example. 300 IN A 192.0.2",
+            "a.example. 300 IN A 192.0.2.1 extra",
+            "example. 300 IN MX mail.example.",
+            "a.example. 300 IN SSHFP 4 2 0123X",
+            "example. 300 IN CAA 0 is-sue \"ca.example\"",
+            "a.example. 300 IN TYPE65280 ABCDEF",
+            "a.example. 300 IN TYPE65280 \\# 2 ABCDEF",
+            "a.example. 300 IN AXFR \\# 0",
+        ];
+        for text in malformed {
+            assert!(text.parse::<DnsRecord>().is_err(), "{}", text);
+        }
+
+        // rdata shorter or longer than its RDLENGTH says, with bytes after
+        // it as the next record would have
+        let fixed = [
+            "a.example. 300 IN A 192.0.2.1",
+            "1.2.0.192.in-addr.arpa. 300 IN PTR a.example.",
+            "example. 3600 IN SOA ns.example. hostmaster.example. 1 3600 600 86400 300",
+            "a.example. 300 IN HINFO \"amd64\" \"Linux 6\"",
+            "example. 300 IN MX 10 mail.example.",
+            "example. 300 IN RP admin.example. info.example.",
+            "example. 300 IN NAPTR 100 10 \"S\" \"SIP+D2U\" \"\" _sip._udp.example.",
+            "old.example. 300 IN DNAME new.example.",
+        ];
+        for text in fixed {
+            for delta in [-1, 1] {
+                let rec: DnsRecord = text.parse().unwrap();
+                let mut buffer = BytePacketBuffer::new();
+                rec.write(&mut buffer).unwrap();
+                buffer.write_bytes(&[0; 4]).unwrap();
+
+                buffer.seek(0).unwrap();
+                buffer.read_qname().unwrap();
+                buffer.step(8).unwrap();
+                let pos = buffer.pos();
+                let len = buffer.read_u16().unwrap();
+                buffer.set_u16(pos, (len as i32 + delta) as u16).unwrap();
+
+                buffer.seek(0).unwrap();
+                assert!(DnsRecord::read(&mut buffer).is_err(), "{} {}", text, delta);
+            }
+        }
+    }
 }
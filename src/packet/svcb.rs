@@ -0,0 +1,359 @@
+//! Service binding parameters carried by SVCB and HTTPS records (RFC 9460).
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::buffer::BytePacketBuffer;
+use super::encoding::{from_base64, quote_character_string, to_base64, unescape};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SvcParam {
+    Mandatory(Vec<u16>),     // 0
+    Alpn(Vec<String>),       // 1
+    NoDefaultAlpn,           // 2
+    Port(u16),               // 3
+    Ipv4Hint(Vec<Ipv4Addr>), // 4
+    Ech(Vec<u8>),            // 5
+    Ipv6Hint(Vec<Ipv6Addr>), // 6
+    Unknown(u16, Vec<u8>),
+}
+
+impl SvcParam {
+    pub fn key(&self) -> u16 {
+        match *self {
+            SvcParam::Mandatory(_) => 0,
+            SvcParam::Alpn(_) => 1,
+            SvcParam::NoDefaultAlpn => 2,
+            SvcParam::Port(_) => 3,
+            SvcParam::Ipv4Hint(_) => 4,
+            SvcParam::Ech(_) => 5,
+            SvcParam::Ipv6Hint(_) => 6,
+            SvcParam::Unknown(key, _) => key,
+        }
+    }
+
+    /// Read one SvcParamKey / SvcParamValue pair.
+    pub fn read(buffer: &mut BytePacketBuffer) -> Result<SvcParam> {
+        let key = buffer.read_u16()?;
+        let len = buffer.read_u16()? as usize;
+        let value = buffer.read_bytes(len)?;
+
+        let param = match key {
+            0 => {
+                if value.is_empty() || value.len() % 2 != 0 {
+                    bail!("malformed mandatory SvcParam of {} bytes", len);
+                }
+                SvcParam::Mandatory(
+                    value
+                        .chunks(2)
+                        .map(|c| ((c[0] as u16) << 8) | c[1] as u16)
+                        .collect(),
+                )
+            }
+            1 => {
+                let mut ids = Vec::new();
+                let mut rest = &value[..];
+                while let Some((&id_len, tail)) = rest.split_first() {
+                    let id_len = id_len as usize;
+                    if id_len == 0 || id_len > tail.len() {
+                        bail!("malformed alpn SvcParam");
+                    }
+                    ids.push(String::from_utf8(tail[..id_len].to_vec())?);
+                    rest = &tail[id_len..];
+                }
+                if ids.is_empty() {
+                    bail!("alpn SvcParam must list at least one protocol");
+                }
+                SvcParam::Alpn(ids)
+            }
+            2 => {
+                if !value.is_empty() {
+                    bail!("no-default-alpn SvcParam must have an empty value");
+                }
+                SvcParam::NoDefaultAlpn
+            }
+            3 => {
+                if value.len() != 2 {
+                    bail!("port SvcParam must be 2 bytes, got {}", len);
+                }
+                SvcParam::Port(((value[0] as u16) << 8) | value[1] as u16)
+            }
+            4 => {
+                if value.is_empty() || value.len() % 4 != 0 {
+                    bail!("malformed ipv4hint SvcParam of {} bytes", len);
+                }
+                SvcParam::Ipv4Hint(
+                    value
+                        .chunks(4)
+                        .map(|c| Ipv4Addr::new(c[0], c[1], c[2], c[3]))
+                        .collect(),
+                )
+            }
+            5 => SvcParam::Ech(value),
+            6 => {
+                if value.is_empty() || value.len() % 16 != 0 {
+                    bail!("malformed ipv6hint SvcParam of {} bytes", len);
+                }
+                SvcParam::Ipv6Hint(
+                    value
+                        .chunks(16)
+                        .map(|c| {
+                            let mut octets = [0; 16];
+                            octets.copy_from_slice(c);
+                            Ipv6Addr::from(octets)
+                        })
+                        .collect(),
+                )
+            }
+            key => SvcParam::Unknown(key, value),
+        };
+
+        Ok(param)
+    }
+
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        buffer.write_u16(self.key())?;
+
+        let pos = buffer.pos();
+        buffer.write_u16(0)?;
+
+        match self {
+            SvcParam::Mandatory(keys) => {
+                for key in keys {
+                    buffer.write_u16(*key)?;
+                }
+            }
+            SvcParam::Alpn(ids) => {
+                for id in ids {
+                    buffer.write_character_string(id.as_bytes())?;
+                }
+            }
+            SvcParam::NoDefaultAlpn => {}
+            SvcParam::Port(port) => buffer.write_u16(*port)?,
+            SvcParam::Ipv4Hint(addrs) => {
+                for addr in addrs {
+                    buffer.write_bytes(&addr.octets())?;
+                }
+            }
+            SvcParam::Ech(config) => buffer.write_bytes(config)?,
+            SvcParam::Ipv6Hint(addrs) => {
+                for addr in addrs {
+                    buffer.write_bytes(&addr.octets())?;
+                }
+            }
+            SvcParam::Unknown(_, value) => buffer.write_bytes(value)?,
+        }
+
+        let size = buffer.pos() - (pos + 2);
+        buffer.set_u16(pos, size as u16)
+    }
+}
+
+/// Presentation name of a SvcParamKey, `keyNNNNN` for unregistered ones.
+pub fn key_name(key: u16) -> String {
+    match key {
+        0 => "mandatory".to_string(),
+        1 => "alpn".to_string(),
+        2 => "no-default-alpn".to_string(),
+        3 => "port".to_string(),
+        4 => "ipv4hint".to_string(),
+        5 => "ech".to_string(),
+        6 => "ipv6hint".to_string(),
+        key => format!("key{}", key),
+    }
+}
+
+/// The SvcParamKey of a presentation name, the inverse of [`key_name`].
+pub fn key_from_name(name: &str) -> Result<u16> {
+    let key = match name {
+        "mandatory" => 0,
+        "alpn" => 1,
+        "no-default-alpn" => 2,
+        "port" => 3,
+        "ipv4hint" => 4,
+        "ech" => 5,
+        "ipv6hint" => 6,
+        other => other
+            .strip_prefix("key")
+            .filter(|num| !num.is_empty() && num.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|num| num.parse().ok())
+            .ok_or_else(|| anyhow!("unknown SvcParamKey {:?}", name))?,
+    };
+    Ok(key)
+}
+
+/// Check a SvcParams list against the rules of RFC 9460 section 2.2 and 8:
+/// keys strictly increasing, `mandatory` well-formed and satisfied, and
+/// `no-default-alpn` only alongside `alpn`.
+pub fn validate(params: &[SvcParam]) -> Result<()> {
+    if params.iter().any(|p| p.key() == 65535) {
+        bail!("SvcParamKey 65535 is reserved");
+    }
+
+    for pair in params.windows(2) {
+        if pair[0].key() >= pair[1].key() {
+            bail!(
+                "SvcParam keys must be strictly increasing, found {} before {}",
+                key_name(pair[0].key()),
+                key_name(pair[1].key())
+            );
+        }
+    }
+
+    let has_key = |key: u16| params.iter().any(|p| p.key() == key);
+
+    if let Some(SvcParam::Mandatory(keys)) = params.first() {
+        for (i, key) in keys.iter().enumerate() {
+            if *key == 0 {
+                bail!("mandatory SvcParam must not list itself");
+            }
+            if keys[..i].contains(key) {
+                bail!("mandatory SvcParam lists {} twice", key_name(*key));
+            }
+            if !has_key(*key) {
+                bail!("mandatory SvcParam {} is missing", key_name(*key));
+            }
+        }
+    }
+
+    if has_key(2) && !has_key(1) {
+        bail!("no-default-alpn requires alpn to be present");
+    }
+
+    Ok(())
+}
+
+/// Escape a single value-list item so that commas survive a round trip.
+fn escape_item(item: &str) -> String {
+    let mut out = String::with_capacity(item.len());
+    for c in item.chars() {
+        match c {
+            // value-list escaping, then escaped again as a character-string
+            ',' => out.push_str("\\\\,"),
+            '\\' => out.push_str("\\\\\\\\"),
+            '"' => out.push_str("\\\""),
+            c if c.is_ascii_graphic() => out.push(c),
+            c => {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    out.push_str(&format!("\\{:03}", b));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Split an unescaped value-list at its commas, resolving the `\,` and
+/// `\\` escapes of the items (RFC 9460, appendix A.1).
+fn split_items(value: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut items = vec![Vec::new()];
+    let mut bytes = value.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            b'\\' => match bytes.next() {
+                Some(&escaped) => items.last_mut().unwrap().push(escaped),
+                None => bail!("dangling backslash in value-list"),
+            },
+            b',' => items.push(Vec::new()),
+            b => items.last_mut().unwrap().push(b),
+        }
+    }
+    if items.iter().any(|item| item.is_empty()) {
+        bail!("empty item in value-list");
+    }
+    Ok(items)
+}
+
+/// Parse every comma-separated item of a value-list as a `T`.
+fn parse_items<T: FromStr>(value: &[u8]) -> Result<Vec<T>>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    split_items(value)?
+        .iter()
+        .map(|item| Ok(std::str::from_utf8(item)?.parse()?))
+        .collect()
+}
+
+fn join<T: ToString>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl fmt::Display for SvcParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = key_name(self.key());
+        match self {
+            SvcParam::Mandatory(keys) => {
+                let names: Vec<_> = keys.iter().map(|k| key_name(*k)).collect();
+                write!(f, "{}={}", name, names.join(","))
+            }
+            SvcParam::Alpn(ids) => {
+                let ids: Vec<_> = ids.iter().map(|id| escape_item(id)).collect();
+                write!(f, "{}=\"{}\"", name, ids.join(","))
+            }
+            SvcParam::NoDefaultAlpn => write!(f, "{}", name),
+            SvcParam::Port(port) => write!(f, "{}={}", name, port),
+            SvcParam::Ipv4Hint(addrs) => write!(f, "{}={}", name, join(addrs)),
+            SvcParam::Ech(config) => write!(f, "{}={}", name, to_base64(config)),
+            SvcParam::Ipv6Hint(addrs) => write!(f, "{}={}", name, join(addrs)),
+            SvcParam::Unknown(_, value) => {
+                write!(f, "{}={}", name, quote_character_string(value))
+            }
+        }
+    }
+}
+
+impl FromStr for SvcParam {
+    type Err = anyhow::Error;
+
+    /// Parse `key=value`, or a bare `key`, as written in the rdata of a
+    /// SVCB or HTTPS record. Escapes in the value are still in place.
+    fn from_str(s: &str) -> Result<SvcParam> {
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name, Some(unescape(value)?)),
+            None => (s, None),
+        };
+        let key = key_from_name(name)?;
+
+        let param = match (key, value) {
+            (2, None) => SvcParam::NoDefaultAlpn,
+            (2, Some(_)) => bail!("no-default-alpn takes no value"),
+            (0..=6, None) => bail!("SvcParam {} needs a value", name),
+            (0, Some(value)) => SvcParam::Mandatory(
+                split_items(&value)?
+                    .iter()
+                    .map(|item| key_from_name(&String::from_utf8_lossy(item)))
+                    .collect::<Result<_>>()?,
+            ),
+            (1, Some(value)) => SvcParam::Alpn(
+                split_items(&value)?
+                    .into_iter()
+                    .map(String::from_utf8)
+                    .collect::<Result<_, _>>()?,
+            ),
+            (3, Some(value)) => SvcParam::Port(
+                std::str::from_utf8(&value)?
+                    .parse()
+                    .with_context(|| format!("invalid port in {:?}", s))?,
+            ),
+            (4, Some(value)) => SvcParam::Ipv4Hint(
+                parse_items(&value).with_context(|| format!("invalid ipv4hint in {:?}", s))?,
+            ),
+            (5, Some(value)) => SvcParam::Ech(from_base64(std::str::from_utf8(&value)?)?),
+            (6, Some(value)) => SvcParam::Ipv6Hint(
+                parse_items(&value).with_context(|| format!("invalid ipv6hint in {:?}", s))?,
+            ),
+            (key, value) => SvcParam::Unknown(key, value.unwrap_or_default()),
+        };
+
+        Ok(param)
+    }
+}
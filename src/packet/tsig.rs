@@ -0,0 +1,376 @@
+//! Transaction signatures (RFC 8945), authenticating messages with a key
+//! shared between two servers. Only HMAC-SHA256 is supported.
+//!
+//! The TSIG record is always the last record of a message and covers all
+//! the bytes before it, so it is picked out by `DnsPacket` when reading and
+//! appended to the written message by [`Tsig::write`] when signing.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::buffer::BytePacketBuffer;
+use super::class::DnsClass;
+use super::name;
+use super::qtype::QueryType;
+use super::rscode::ResultCode;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const HMAC_SHA256: &str = "hmac-sha256.";
+
+/// The error a TSIG record carries for a bad MAC, which shares its code
+/// with BADVERS. Like BADKEY and BADTIME it only appears in the record
+/// itself, with NOTAUTH in the header.
+pub const BADSIG: ResultCode = ResultCode::BADVERS;
+
+/// How far the signer's clock may be off from ours, in seconds.
+const FUDGE: u16 = 300;
+
+/// Room a TSIG record with an HMAC-SHA256 MAC needs beyond its key name.
+const RECORD_SIZE: usize = 10 + HMAC_SHA256.len() + 1 + 16 + 32 + 6;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tsig {
+    pub key_name: String,
+    pub algorithm: String,
+    /// Seconds since the epoch, in 48 bits.
+    pub time_signed: u64,
+    pub fudge: u16,
+    pub mac: Vec<u8>,
+    /// The message ID when it was signed, in case a forwarder changed it.
+    pub original_id: u16,
+    pub error: ResultCode,
+    pub other: Vec<u8>,
+    /// Where the record starts in the message it was read from.
+    offset: usize,
+}
+
+/// A key as both ends know it: its name and the shared secret.
+#[derive(Debug, Clone, Copy)]
+pub struct Key<'a> {
+    pub name: &'a str,
+    pub secret: &'a [u8],
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// A name in canonical wire format: lower case and uncompressed.
+fn wire_name(name: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for label in name::labels(name) {
+        let label = name::unescape_label(label)?;
+        out.push(label.len() as u8);
+        out.extend(label.to_ascii_lowercase());
+    }
+    out.push(0);
+    Ok(out)
+}
+
+impl Tsig {
+    /// Whether the record at the buffer's position is a TSIG record.
+    pub fn is_next(buffer: &mut BytePacketBuffer) -> Result<bool> {
+        let start = buffer.pos();
+        buffer.read_qname()?;
+        let qtype = buffer.read_u16()?;
+        buffer.seek(start)?;
+        Ok(qtype == QueryType::TSIG.to_num())
+    }
+
+    pub fn read(buffer: &mut BytePacketBuffer) -> Result<Tsig> {
+        let offset = buffer.pos();
+        let key_name = buffer.read_qname()?;
+        let _ = buffer.read_u16()?; // type
+        let class = DnsClass::from_num(buffer.read_u16()?);
+        let _ = buffer.read_u32()?; // TTL
+        if class != DnsClass::ANY {
+            bail!("TSIG record of class {} instead of ANY", class);
+        }
+        let data_len = buffer.read_u16()?;
+        let data_end = buffer.pos() + data_len as usize;
+
+        let algorithm = buffer.read_qname()?;
+        let time_signed = ((buffer.read_u16()? as u64) << 32) | buffer.read_u32()? as u64;
+        let fudge = buffer.read_u16()?;
+        let mac_len = buffer.read_u16()?;
+        let mac = buffer.read_bytes(mac_len as usize)?;
+        let original_id = buffer.read_u16()?;
+        let error = ResultCode::from_num(buffer.read_u16()?);
+        let other_len = buffer.read_u16()?;
+        let other = buffer.read_bytes(other_len as usize)?;
+        if buffer.pos() != data_end {
+            bail!("malformed TSIG record of {} bytes", data_len);
+        }
+
+        Ok(Tsig {
+            key_name,
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+            offset,
+        })
+    }
+
+    /// Append the record to the message in `buffer`, counting it in the
+    /// header.
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        buffer.write_bytes(&wire_name(&self.key_name)?)?;
+        buffer.write_u16(QueryType::TSIG.to_num())?;
+        buffer.write_u16(DnsClass::ANY.to_num())?;
+        buffer.write_u32(0)?;
+
+        let pos = buffer.pos();
+        buffer.write_u16(0)?;
+        buffer.write_bytes(&wire_name(&self.algorithm)?)?;
+        buffer.write_u16((self.time_signed >> 32) as u16)?;
+        buffer.write_u32(self.time_signed as u32)?;
+        buffer.write_u16(self.fudge)?;
+        buffer.write_u16(self.mac.len() as u16)?;
+        buffer.write_bytes(&self.mac)?;
+        buffer.write_u16(self.original_id)?;
+        buffer.write_u16(self.error.to_num())?;
+        buffer.write_u16(self.other.len() as u16)?;
+        buffer.write_bytes(&self.other)?;
+        let size = buffer.pos() - (pos + 2);
+        buffer.set_u16(pos, size as u16)?;
+
+        let additional = ((buffer.get(10)? as u16) << 8) | buffer.get(11)? as u16;
+        buffer.set_u16(10, additional + 1)
+    }
+
+    /// Room the signature of a message takes with the key `key_name`.
+    pub fn size(key_name: &str) -> usize {
+        name::wire_len(key_name) + RECORD_SIZE
+    }
+
+    /// Sign the message written to `buffer` with `key`.
+    ///
+    /// A response covers the MAC of the request it answers, and every later
+    /// message of a multi-message response the MAC of the one before, as
+    /// `prior_mac`. Those later messages only cover the time, as
+    /// `timers_only` tells.
+    pub fn sign(
+        buffer: &BytePacketBuffer,
+        key: &Key,
+        prior_mac: Option<&[u8]>,
+        timers_only: bool,
+        error: ResultCode,
+    ) -> Result<Tsig> {
+        let message = &buffer.buf[..buffer.pos()];
+        let mut tsig = Tsig {
+            key_name: key.name.to_string(),
+            algorithm: HMAC_SHA256.to_string(),
+            time_signed: now(),
+            fudge: FUDGE,
+            mac: Vec::new(),
+            original_id: ((message[0] as u16) << 8) | message[1] as u16,
+            error,
+            other: Vec::new(),
+            offset: 0,
+        };
+        if error == ResultCode::BADTIME {
+            // tell the signer what our clock says
+            tsig.other = tsig.time_signed.to_be_bytes()[2..].to_vec();
+        }
+        tsig.mac = tsig.digest(key.secret, message, prior_mac, timers_only)?;
+        Ok(tsig)
+    }
+
+    /// A TSIG record without a MAC, reporting `error` for a request we could
+    /// not check the signature of.
+    pub fn unsigned(request: &Tsig, error: ResultCode) -> Tsig {
+        Tsig {
+            mac: Vec::new(),
+            error,
+            other: Vec::new(),
+            time_signed: now(),
+            ..request.clone()
+        }
+    }
+
+    /// Check the signature of the message in `buffer`, which this record was
+    /// read from. Returns the TSIG error to answer with if it doesn't hold.
+    pub fn verify(
+        &self,
+        buffer: &BytePacketBuffer,
+        secret: &[u8],
+        prior_mac: Option<&[u8]>,
+        timers_only: bool,
+    ) -> std::result::Result<(), ResultCode> {
+        if !name::eq(&self.algorithm, HMAC_SHA256) {
+            return Err(ResultCode::BADKEY);
+        }
+
+        // the message as it was before the record was added
+        let mut message = buffer.buf[..self.offset].to_vec();
+        if message.len() < 12 {
+            return Err(BADSIG);
+        }
+        message[..2].copy_from_slice(&self.original_id.to_be_bytes());
+        let additional = u16::from_be_bytes([message[10], message[11]]);
+        message[10..12].copy_from_slice(&additional.saturating_sub(1).to_be_bytes());
+
+        let expected = self
+            .digest(secret, &message, prior_mac, timers_only)
+            .map_err(|_| BADSIG)?;
+        if expected.len() != self.mac.len()
+            || !expected
+                .iter()
+                .zip(&self.mac)
+                .fold(true, |eq, (a, b)| eq & (a == b))
+        {
+            return Err(BADSIG);
+        }
+
+        if now().abs_diff(self.time_signed) > self.fudge as u64 {
+            return Err(ResultCode::BADTIME);
+        }
+        Ok(())
+    }
+
+    /// The HMAC over the prior MAC, the message and the TSIG variables
+    /// (RFC 8945, section 4.3.3).
+    fn digest(
+        &self,
+        secret: &[u8],
+        message: &[u8],
+        prior_mac: Option<&[u8]>,
+        timers_only: bool,
+    ) -> Result<Vec<u8>> {
+        let mut mac = HmacSha256::new_from_slice(secret)?;
+        if let Some(prior_mac) = prior_mac {
+            mac.update(&(prior_mac.len() as u16).to_be_bytes());
+            mac.update(prior_mac);
+        }
+        mac.update(message);
+
+        if !timers_only {
+            mac.update(&wire_name(&self.key_name)?);
+            mac.update(&DnsClass::ANY.to_num().to_be_bytes());
+            mac.update(&0u32.to_be_bytes());
+            mac.update(&wire_name(&self.algorithm)?);
+        }
+        mac.update(&self.time_signed.to_be_bytes()[2..]);
+        mac.update(&self.fudge.to_be_bytes());
+        if !timers_only {
+            mac.update(&self.error.to_num().to_be_bytes());
+            mac.update(&(self.other.len() as u16).to_be_bytes());
+            mac.update(&self.other);
+        }
+
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{question::DnsQuestion, DnsPacket};
+
+    const KEY: Key = Key {
+        name: "xfr-key.",
+        secret: b"0123456789abcdef0123456789abcdef",
+    };
+
+    fn message() -> BytePacketBuffer {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 0x4321;
+        packet.questions.push(DnsQuestion::new(
+            "example.com.".to_string(),
+            QueryType::AXFR,
+        ));
+        let mut buffer = BytePacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn signed() -> BytePacketBuffer {
+        let mut buffer = message();
+        Tsig::sign(&buffer, &KEY, None, false, ResultCode::NOERROR)
+            .unwrap()
+            .write(&mut buffer)
+            .unwrap();
+        buffer
+    }
+
+    /// The TSIG record of the message in `buffer`, as the receiver reads it.
+    fn received(buffer: &mut BytePacketBuffer) -> Tsig {
+        buffer.seek(0).unwrap();
+        DnsPacket::from_buffer(buffer).unwrap().tsig.unwrap()
+    }
+
+    #[test]
+    fn verifies_what_it_signs() {
+        let mut buffer = message();
+        let tsig = Tsig::sign(&buffer, &KEY, None, false, ResultCode::NOERROR).unwrap();
+        tsig.write(&mut buffer).unwrap();
+
+        let read = received(&mut buffer);
+        assert_eq!(read.mac, tsig.mac);
+        assert_eq!(read.verify(&buffer, KEY.secret, None, false), Ok(()));
+
+        // a response covers the request's MAC
+        let mut response = message();
+        let reply =
+            Tsig::sign(&response, &KEY, Some(&tsig.mac), false, ResultCode::NOERROR).unwrap();
+        reply.write(&mut response).unwrap();
+        let read = received(&mut response);
+        assert_eq!(
+            read.verify(&response, KEY.secret, Some(&tsig.mac), false),
+            Ok(())
+        );
+        assert_eq!(read.verify(&response, KEY.secret, None, false), Err(BADSIG));
+    }
+
+    #[test]
+    fn rejects_a_bad_mac() {
+        let mut buffer = message();
+        let mut tsig = Tsig::sign(&buffer, &KEY, None, false, ResultCode::NOERROR).unwrap();
+        tsig.mac[0] ^= 1;
+        tsig.write(&mut buffer).unwrap();
+        let read = received(&mut buffer);
+        assert_eq!(read.verify(&buffer, KEY.secret, None, false), Err(BADSIG));
+
+        // or a message changed after signing
+        let mut buffer = signed();
+        buffer.set(3, 1).unwrap();
+        let read = received(&mut buffer);
+        assert_eq!(read.verify(&buffer, KEY.secret, None, false), Err(BADSIG));
+
+        // or another secret
+        let mut buffer = signed();
+        let read = received(&mut buffer);
+        assert_eq!(
+            read.verify(&buffer, b"another secret", None, false),
+            Err(BADSIG)
+        );
+    }
+
+    #[test]
+    fn rejects_a_time_outside_the_fudge() {
+        let mut buffer = message();
+        let mut tsig = Tsig::sign(&buffer, &KEY, None, false, ResultCode::NOERROR).unwrap();
+        tsig.time_signed -= FUDGE as u64 + 60;
+        tsig.mac = tsig
+            .digest(KEY.secret, &buffer.buf[..buffer.pos()], None, false)
+            .unwrap();
+        tsig.write(&mut buffer).unwrap();
+
+        let read = received(&mut buffer);
+        assert_eq!(
+            read.verify(&buffer, KEY.secret, None, false),
+            Err(ResultCode::BADTIME)
+        );
+    }
+}
@@ -1,110 +1,1200 @@
-use std::net::{IpAddr, UdpSocket};
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
 
+use crate::cache::Cache;
+use crate::config::{Config, Mode};
 use crate::packet::{
-    buffer::BytePacketBuffer, qtype::QueryType, question::DnsQuestion, rscode::ResultCode,
+    buffer::BytePacketBuffer,
+    class::DnsClass,
+    edns::{ede, ClientSubnet, Edns, EdnsOption, DEFAULT_UDP_PAYLOAD_SIZE, MIN_UDP_PAYLOAD_SIZE},
+    header::DnsHeader,
+    name,
+    opcode::Opcode,
+    qtype::QueryType,
+    question::DnsQuestion,
+    record::DnsRecord,
+    rscode::ResultCode,
+    tsig::{self, Tsig},
     DnsPacket,
 };
+use crate::zone::{update, Catalog};
+use crate::{chaos, cookie};
 
-use anyhow::Result;
-use rand::random;
+use anyhow::{anyhow, bail, Result};
+use log::{debug, info, warn};
+use rand::{random, seq::SliceRandom};
 
-// const FORWARD_SERVER: &str = "223.5.5.5"; // use Alibaba's public DNS to forward queries.
-const ROOT_SERVER: &str = "192.5.5.241"; // f.root-server.net
+/// A resolution failure, along with the Extended DNS Error (RFC 8914) that
+/// explains it to the client.
+#[derive(Debug)]
+pub struct ResolveError {
+    pub info_code: u16,
+    pub reason: String,
+}
+
+impl ResolveError {
+    pub fn new(info_code: u16, reason: String) -> Self {
+        ResolveError { info_code, reason }
+    }
 
-fn lookup(qname: &str, qtype: QueryType, server: (IpAddr, u16)) -> Result<DnsPacket> {
-    let socket = UdpSocket::bind(("0.0.0.0", 43210))?;
+    pub fn to_option(&self) -> EdnsOption {
+        EdnsOption::ExtendedError {
+            info_code: self.info_code,
+            extra_text: self.reason.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", ede::name(self.info_code), self.reason)
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// How a query is put to a server.
+#[derive(Debug, Clone)]
+pub struct QueryOptions {
+    pub class: DnsClass,
+    /// Send the query over TCP rather than UDP.
+    pub tcp: bool,
+    pub recursion_desired: bool,
+    pub checking_disabled: bool,
+    pub dnssec_ok: bool,
+    /// Payload size to advertise in an OPT record, or `None` to send the
+    /// query without EDNS, and so without a cookie.
+    pub edns_size: Option<u16>,
+    /// Passed on so the server can tailor its answer.
+    pub client_subnet: Option<ClientSubnet>,
+    pub timeout: Duration,
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        QueryOptions {
+            class: DnsClass::IN,
+            tcp: false,
+            recursion_desired: true,
+            checking_disabled: false,
+            dnssec_ok: false,
+            edns_size: Some(DEFAULT_UDP_PAYLOAD_SIZE),
+            client_subnet: None,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Ask `server` about `qname`, giving up after `timeout`. If `client_subnet`
+/// is given, it is passed on so the server can tailor its answer.
+pub fn lookup(
+    qname: &str,
+    qtype: QueryType,
+    server: SocketAddr,
+    client_subnet: Option<&ClientSubnet>,
+    timeout: Duration,
+) -> Result<DnsPacket> {
+    let options = QueryOptions {
+        client_subnet: client_subnet.copied(),
+        timeout,
+        ..QueryOptions::default()
+    };
+    query(qname, qtype, server, &options)
+}
+
+/// Ask `server` about `qname` the way `options` say.
+pub fn query(
+    qname: &str,
+    qtype: QueryType,
+    server: SocketAddr,
+    options: &QueryOptions,
+) -> Result<DnsPacket> {
+    let res = exchange(qname, qtype, server, options)?;
+    if res.header.rescode == ResultCode::BADCOOKIE {
+        // The server handed us a fresh cookie along with the error.
+        info!("retrying {} with a new server cookie", server);
+        return exchange(qname, qtype, server, options);
+    }
+    Ok(res)
+}
+
+/// Send a single query to `server` and wait for its answer, ignoring
+/// packets that don't match the query's ID or our client cookie.
+fn exchange(
+    qname: &str,
+    qtype: QueryType,
+    server: SocketAddr,
+    options: &QueryOptions,
+) -> Result<DnsPacket> {
+    let network_error = |e: std::io::Error| {
+        let reason = match e.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => format!("{}: timed out", server),
+            _ => format!("{}: {}", server, e),
+        };
+        ResolveError::new(ede::NETWORK_ERROR, reason)
+    };
 
     let mut packet = DnsPacket::new();
 
     packet.header.id = random();
-    packet.header.questions = 1;
-    packet.header.recursion_desired = true;
-    packet
-        .questions
-        .push(DnsQuestion::new(qname.to_string(), qtype));
+    packet.header.recursion_desired = options.recursion_desired;
+    packet.header.checking_disabled = options.checking_disabled;
+    packet.questions.push(DnsQuestion {
+        name: qname.to_string(),
+        qtype,
+        class: options.class,
+    });
+    let client_cookie = cookie::client_cookie(server.ip());
+    if let Some(size) = options.edns_size {
+        // Ask for EDNS so that upstream servers can explain their failures.
+        let mut edns = Edns {
+            udp_payload_size: size,
+            dnssec_ok: options.dnssec_ok,
+            ..Edns::default()
+        };
+        edns.options.push(EdnsOption::Cookie {
+            client: client_cookie,
+            server: cookie::server_cookie(server.ip()),
+        });
+        if let Some(subnet) = options.client_subnet {
+            edns.options.push(EdnsOption::ClientSubnet(subnet));
+        }
+        packet.edns = Some(edns);
+    }
 
     let mut req_buffer = BytePacketBuffer::new();
     packet.write(&mut req_buffer)?;
-    socket.send_to(&req_buffer.buf[0..req_buffer.pos()], server)?;
 
-    let mut res_buffer = BytePacketBuffer::new();
-    socket.recv_from(&mut res_buffer.buf)?;
+    if options.tcp {
+        let mut stream =
+            TcpStream::connect_timeout(&server, options.timeout).map_err(network_error)?;
+        stream.set_read_timeout(Some(options.timeout))?;
+        stream.set_write_timeout(Some(options.timeout))?;
+        req_buffer.write_tcp(&mut stream).map_err(network_error)?;
 
-    DnsPacket::from_buffer(&mut res_buffer)
+        // Nobody else can inject packets into the connection, so a mismatch
+        // means the server is broken; a message we cannot read is skipped
+        // in case a good one follows before the timeout.
+        loop {
+            let mut res_buffer = BytePacketBuffer::read_tcp(&mut stream)
+                .map_err(network_error)?
+                .ok_or_else(|| network_error(ErrorKind::UnexpectedEof.into()))?;
+            let res = match parse_answer(&mut res_buffer, server) {
+                Some(res) => res,
+                None => continue,
+            };
+
+            if res.header.id != packet.header.id || !accept_cookie(&res, &client_cookie, server) {
+                let reason = format!("answer from {} does not match the query", server);
+                return Err(ResolveError::new(ede::INVALID_DATA, reason).into());
+            }
+            return Ok(res);
+        }
+    }
+
+    // a fresh random port for every query makes forged answers harder to
+    // get accepted (RFC 5452)
+    let local = match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.set_read_timeout(Some(options.timeout))?;
+    socket
+        .send_to(&req_buffer.buf[0..req_buffer.pos()], server)
+        .map_err(network_error)?;
+
+    loop {
+        let (mut res_buffer, from) = BytePacketBuffer::recv_from(&socket).map_err(network_error)?;
+
+        if from != server {
+            warn!("discarding unexpected answer from {}", from);
+            continue;
+        }
+        // forged or broken packets are ignored while waiting for the answer
+        let res = match parse_answer(&mut res_buffer, from) {
+            Some(res) => res,
+            None => continue,
+        };
+        if res.header.id != packet.header.id {
+            warn!("discarding unexpected answer from {}", from);
+            continue;
+        }
+        if !accept_cookie(&res, &client_cookie, server) {
+            warn!("discarding answer from {} with a forged cookie", from);
+            continue;
+        }
+
+        return Ok(res);
+    }
 }
 
-pub fn query_handler(socket: &UdpSocket) -> Result<()> {
-    let mut req_buffer = BytePacketBuffer::new();
-    let (_, src) = socket.recv_from(&mut req_buffer.buf)?;
+/// Read the answer in `res_buffer`, `None` if it is malformed and to be
+/// ignored.
+fn parse_answer(res_buffer: &mut BytePacketBuffer, from: SocketAddr) -> Option<DnsPacket> {
+    match DnsPacket::from_buffer(res_buffer) {
+        Ok(res) => Some(res),
+        Err(e) => {
+            warn!("discarding malformed answer from {}: {:#}", from, e);
+            None
+        }
+    }
+}
+
+/// Whether `res` echoes our client cookie, if it carries a cookie at all.
+/// The server cookie that comes with it is remembered for the next query.
+fn accept_cookie(res: &DnsPacket, client_cookie: &[u8; 8], server: SocketAddr) -> bool {
+    if let Some((client, server_cookie)) = res.edns.as_ref().and_then(|e| e.cookie()) {
+        if client != client_cookie {
+            return false;
+        }
+        if !server_cookie.is_empty() {
+            cookie::remember_server_cookie(server.ip(), server_cookie);
+        }
+    }
+    true
+}
+
+/// The transport a query arrived over, which decides how large the answer
+/// may be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
 
-    let mut req = DnsPacket::from_buffer(&mut req_buffer)?;
+/// Answer one query arriving on `socket`.
+pub fn query_handler(
+    socket: &UdpSocket,
+    config: &Config,
+    cache: &Cache,
+    zones: &Catalog,
+) -> Result<()> {
+    let (mut req_buffer, src) = BytePacketBuffer::recv_from(socket)?;
+
+    for res_buffer in handle_query(&mut req_buffer, src, Transport::Udp, config, cache, zones)? {
+        socket.send_to(&res_buffer.buf[..res_buffer.pos()], src)?;
+    }
+
+    Ok(())
+}
+
+/// Answer the queries a client sends over a TCP connection, until it closes
+/// the connection or leaves it idle for too long.
+pub fn tcp_query_handler(
+    mut stream: TcpStream,
+    config: &Config,
+    cache: &Cache,
+    zones: &Catalog,
+) -> Result<()> {
+    let src = stream.peer_addr()?;
+    stream.set_read_timeout(Some(config.timeouts.tcp_idle))?;
+
+    loop {
+        let mut req_buffer = match BytePacketBuffer::read_tcp(&mut stream) {
+            Ok(Some(buffer)) => buffer,
+            Ok(None) => return Ok(()),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                debug!("closing idle connection from {}", src);
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        for res_buffer in handle_query(&mut req_buffer, src, Transport::Tcp, config, cache, zones)?
+        {
+            res_buffer.write_tcp(&mut stream)?;
+        }
+    }
+}
+
+/// Work out the answer to the query in `req_buffer`, if it deserves one.
+/// Zone transfers are answered with several messages.
+fn handle_query(
+    req_buffer: &mut BytePacketBuffer,
+    src: SocketAddr,
+    transport: Transport,
+    config: &Config,
+    cache: &Cache,
+    zones: &Catalog,
+) -> Result<Vec<BytePacketBuffer>> {
+    let mut req = match DnsPacket::from_buffer(req_buffer) {
+        Ok(req) => req,
+        Err(e) => return malformed(req_buffer, src, config, e),
+    };
+
+    if req.header.response {
+        // Never answer a response, or we could be made to bounce packets
+        // back and forth with another server.
+        warn!("Dropping stray response from {}", src);
+        return Ok(Vec::new());
+    }
 
     let mut packet = DnsPacket::new();
     packet.header.id = req.header.id;
-    packet.header.recursion_desired = true;
-    packet.header.recursion_available = true;
+    packet.header.opcode = req.header.opcode;
+    packet.header.recursion_desired = req.header.recursion_desired;
+    packet.header.recursion_available = config.mode != Mode::Authoritative;
     packet.header.response = true;
 
-    let mut is_form_error = true;
-    while let Some(question) = req.questions.pop() {
-        println!("Received query: {:?}", question);
-        if let Ok(result) = recursive_lookup(&question.name, question.qtype) {
-            packet.questions.push(question);
+    let mut has_valid_cookie = false;
+    if let Some(edns) = &req.edns {
+        let mut response_edns = Edns::default();
+        if let (true, Some((client, server))) = (config.cookies.enabled, edns.cookie()) {
+            let secret = &config.cookies.secret;
+            has_valid_cookie = cookie::check_server_cookie(secret, client, server, src.ip());
+            // always hand out a fresh cookie, so the client's never expires
+            response_edns.options.push(EdnsOption::Cookie {
+                client: *client,
+                server: cookie::make_server_cookie(secret, client, src.ip()),
+            });
+        }
+        packet.edns = Some(response_edns);
+    }
+    let limit = match transport {
+        Transport::Tcp => u16::MAX as usize,
+        Transport::Udp => {
+            // Clients without EDNS can only take the classic 512 octets.
+            let mut limit = req
+                .edns
+                .as_ref()
+                .map_or(MIN_UDP_PAYLOAD_SIZE, |e| e.udp_payload_size as usize)
+                .max(MIN_UDP_PAYLOAD_SIZE);
+            if let (false, Some(cookieless)) =
+                (has_valid_cookie, config.cookies.cookieless_udp_limit)
+            {
+                limit = limit.min(cookieless);
+            }
+            limit
+        }
+    };
+
+    if !config.acl.allows(src.ip()) {
+        info!("Refusing query from {}", src);
+        packet.questions.extend(req.questions.drain(..).take(1));
+        packet.header.rescode = ResultCode::REFUSED;
+        let reason = format!("{} may not query this server", src.ip());
+        add_extended_error(&mut packet, ede::PROHIBITED, reason);
+        return respond(&mut packet, src, limit, None);
+    }
+
+    // Responses to a signed request are signed with the same key, or carry
+    // the reason they cannot be.
+    let mut signer = None;
+    if let Some(request) = &req.tsig {
+        let key = config.tsig_key(&request.key_name).map(|key| tsig::Key {
+            name: &key.name,
+            secret: &key.secret,
+        });
+        let (signing, verified) = match key {
+            None => {
+                let unsigned = Tsig::unsigned(request, ResultCode::BADKEY);
+                (Signer::Unsigned(unsigned), Err(ResultCode::BADKEY))
+            }
+            Some(key) => match request.verify(req_buffer, key.secret, None, false) {
+                Ok(()) => (Signer::new(key, request, ResultCode::NOERROR), Ok(())),
+                // signed, so the client can trust the time we tell it
+                Err(ResultCode::BADTIME) => (
+                    Signer::new(key, request, ResultCode::BADTIME),
+                    Err(ResultCode::BADTIME),
+                ),
+                Err(error) => (Signer::Unsigned(Tsig::unsigned(request, error)), Err(error)),
+            },
+        };
+        signer = Some(signing);
+        if let Err(error) = verified {
+            info!(
+                "Bad signature from {} with key {}: TSIG error {}",
+                src,
+                request.key_name,
+                error.to_num()
+            );
+            packet.questions.extend(req.questions.drain(..).take(1));
+            packet.header.rescode = ResultCode::NOTAUTH;
+            return respond(&mut packet, src, limit, signer.as_mut());
+        }
+    }
+
+    if let Some(version) = req.edns.as_ref().map(|e| e.version).filter(|v| *v > 0) {
+        info!("EDNS version {} is not supported", version);
+        packet.questions = req.questions;
+        packet.header.rescode = ResultCode::BADVERS;
+        return respond(&mut packet, src, limit, signer.as_mut());
+    }
+
+    if req.header.opcode == Opcode::NOTIFY {
+        return notified(
+            &mut req,
+            &mut packet,
+            src,
+            limit,
+            config,
+            zones,
+            signer.as_mut(),
+        );
+    }
+    if req.header.opcode == Opcode::UPDATE {
+        return updated(
+            &mut req,
+            &mut packet,
+            src,
+            limit,
+            config,
+            zones,
+            signer.as_mut(),
+        );
+    }
+
+    if req.header.opcode != Opcode::QUERY {
+        info!("Opcode {:?} is not implemented", req.header.opcode);
+        packet.questions = req.questions;
+        packet.header.rescode = ResultCode::NOTIMP;
+        let reason = format!("opcode {:?} is not supported", req.header.opcode);
+        add_extended_error(&mut packet, ede::NOT_SUPPORTED, reason);
+        return respond(&mut packet, src, limit, signer.as_mut());
+    }
+
+    // A message carries exactly one question in practice (RFC 9619); with
+    // none or several there is nothing a client could match an answer to.
+    if req.questions.len() != 1 {
+        info!("Expected one question, got {}", req.questions.len());
+        packet.questions.extend(req.questions.drain(..).take(1));
+        packet.header.rescode = ResultCode::FORMERR;
+        return respond(&mut packet, src, limit, signer.as_mut());
+    }
+
+    let question = req.questions.remove(0);
+    info!("Received query: {:?}", question);
+    // echoed exactly as received, case included, so the client can match it
+    packet.questions.push(question.clone());
+
+    match question.class {
+        DnsClass::IN => {}
+        DnsClass::CH => {
+            // CHAOS-class queries are about this server itself and are
+            // never sent upstream.
+            let (rescode, answers) = chaos::answer(&question, &config.identity);
+            packet.header.rescode = rescode;
+            packet.answers.extend(answers);
+            return respond(&mut packet, src, limit, signer.as_mut());
+        }
+        class => {
+            info!("Class {} is not implemented", class);
+            packet.header.rescode = ResultCode::NOTIMP;
+            let reason = format!("class {} is not supported", class);
+            add_extended_error(&mut packet, ede::NOT_SUPPORTED, reason);
+            return respond(&mut packet, src, limit, signer.as_mut());
+        }
+    }
+
+    if matches!(question.qtype, QueryType::AXFR | QueryType::IXFR) {
+        if question.qtype == QueryType::AXFR && transport == Transport::Udp {
+            info!("Refusing zone transfer of {} over UDP", question.name);
+            packet.header.rescode = ResultCode::NOTIMP;
+            let reason = "zone transfers need TCP".to_string();
+            add_extended_error(&mut packet, ede::NOT_SUPPORTED, reason);
+            return respond(&mut packet, src, limit, signer.as_mut());
+        }
+        // IXFR tells the version the secondary has with its SOA
+        let serial = req.authorities.iter().find_map(|rec| match rec {
+            DnsRecord::SOA { serial, .. } => Some(*serial),
+            _ => None,
+        });
+        if question.qtype == QueryType::IXFR && serial.is_none() {
+            info!("IXFR of {} without an SOA to start from", question.name);
+            packet.header.rescode = ResultCode::FORMERR;
+            return respond(&mut packet, src, limit, signer.as_mut());
+        }
+        let serial = serial.filter(|_| question.qtype == QueryType::IXFR);
+        return transfer(
+            &mut packet,
+            src,
+            transport,
+            config,
+            zones,
+            signer.as_mut(),
+            serial,
+        );
+    }
+
+    if config.mode == Mode::Authoritative {
+        match zones.answer(&question.name, question.qtype) {
+            Some(answer) => {
+                packet.header.authoritative_answer = answer.header.authoritative_answer;
+                packet.header.rescode = answer.header.rescode;
+                packet.answers = answer.answers;
+                packet.authorities = answer.authorities;
+                packet.resources = answer.resources;
+            }
+            None => {
+                info!("Refusing query for {} outside our zones", question.name);
+                packet.header.rescode = ResultCode::REFUSED;
+                let reason = format!("not authoritative for {}", question.name);
+                add_extended_error(&mut packet, ede::NOT_AUTHORITATIVE, reason);
+            }
+        }
+        return respond(&mut packet, src, limit, signer.as_mut());
+    }
+
+    let client_subnet = client_subnet(config, &req, src);
+    let cached = cache.get(
+        &question.name,
+        question.qtype,
+        client_subnet.map(|s| s.address),
+    );
+    let result = match cached {
+        Some(result) => {
+            debug!("Answering {} from cache", question.name);
+            Ok(result)
+        }
+        None => resolve(
+            config,
+            &question.name,
+            question.qtype,
+            client_subnet.as_ref(),
+        )
+        .inspect(|result| {
+            cache.insert(
+                &question.name,
+                question.qtype,
+                client_subnet.as_ref(),
+                result,
+            )
+        }),
+    };
+
+    match result {
+        Ok(result) => {
             packet.header.rescode = result.header.rescode;
 
-            println!("Got {} records in result.answers", result.answers.len());
+            // pass on upstream explanations of errors
+            if let (Some(edns), Some(upstream)) = (&mut packet.edns, &result.edns) {
+                edns.options.extend(upstream.extended_errors().cloned());
+            }
+            echo_client_subnet(&req, &mut packet, &result);
+
+            debug!("Got {} records in result.answers", result.answers.len());
             for rec in result.answers {
-                println!("Answer: {:?}", rec);
+                debug!("Answer: {:?}", rec);
                 packet.answers.push(rec);
             }
 
-            println!(
+            debug!(
                 "Got {} records in result.authorities",
                 result.authorities.len()
             );
             for rec in result.authorities {
-                println!("Answer: {:?}", rec);
+                debug!("Answer: {:?}", rec);
                 packet.authorities.push(rec);
             }
 
-            println!("Got {} records in result.resources", result.resources.len());
+            debug!("Got {} records in result.resources", result.resources.len());
             for rec in result.resources {
-                println!("Answer: {:?}", rec);
+                debug!("Answer: {:?}", rec);
                 packet.resources.push(rec);
             }
-        } else {
-            eprintln!("SERVFAIL happend!");
+        }
+        Err(e) => {
+            warn!("SERVFAIL happend: {}", e);
             packet.header.rescode = ResultCode::SERVFAIL;
+            match e.chain().find_map(|e| e.downcast_ref::<ResolveError>()) {
+                Some(err) => add_extended_error(&mut packet, err.info_code, err.reason.clone()),
+                None => add_extended_error(&mut packet, ede::OTHER, e.to_string()),
+            }
         }
-        is_form_error = false;
     }
-    if is_form_error {
-        println!("FORMER happend");
-        packet.header.rescode = ResultCode::FORMERR;
+
+    respond(&mut packet, src, limit, signer.as_mut())
+}
+
+/// The network to reveal upstream on behalf of the client that sent `req`,
+/// if any: the one the client gave, or else the one it connects from,
+/// truncated to the configured prefix length.
+fn client_subnet(config: &Config, req: &DnsPacket, src: SocketAddr) -> Option<ClientSubnet> {
+    let ecs = &config.client_subnet;
+    if !ecs.enabled {
+        return None;
+    }
+
+    let (address, prefix) = match req.edns.as_ref().and_then(|e| e.client_subnet()) {
+        // the client asked us not to reveal its network
+        Some(subnet) if subnet.source_prefix == 0 => return None,
+        Some(subnet) => (subnet.address, subnet.source_prefix),
+        None => (src.ip(), u8::MAX),
+    };
+    let max_prefix = match address {
+        IpAddr::V4(_) => ecs.ipv4_prefix,
+        IpAddr::V6(_) => ecs.ipv6_prefix,
+    };
+    Some(ClientSubnet::new(address, prefix.min(max_prefix)))
+}
+
+/// A client that sent a Client Subnet option gets it back, along with the
+/// scope the answer is valid for (RFC 7871, section 7.2.2).
+fn echo_client_subnet(req: &DnsPacket, packet: &mut DnsPacket, result: &DnsPacket) {
+    let asked = match req.edns.as_ref().and_then(|e| e.client_subnet()) {
+        Some(subnet) => *subnet,
+        None => return,
+    };
+    let scope = result
+        .edns
+        .as_ref()
+        .and_then(|e| e.client_subnet())
+        .map_or(0, |s| s.scope_prefix);
+
+    if let Some(edns) = &mut packet.edns {
+        edns.options.push(EdnsOption::ClientSubnet(ClientSubnet {
+            scope_prefix: scope,
+            ..asked
+        }));
+    }
+}
+
+/// Explain an error to the client, if it understands EDNS.
+fn add_extended_error(packet: &mut DnsPacket, info_code: u16, extra_text: String) {
+    if let Some(edns) = &mut packet.edns {
+        edns.options.push(EdnsOption::ExtendedError {
+            info_code,
+            extra_text,
+        });
+    }
+}
+
+/// Answer a request that cannot be read with FORMERR, as long as its
+/// header can be, so that the client learns why instead of timing out.
+/// Malformed DNS Cookie (RFC 7873, section 5.2.2) and Client Subnet
+/// (RFC 7871, section 7.1) options are among the reasons.
+fn malformed(
+    req_buffer: &mut BytePacketBuffer,
+    src: SocketAddr,
+    config: &Config,
+    error: anyhow::Error,
+) -> Result<Vec<BytePacketBuffer>> {
+    let mut header = DnsHeader::new();
+    if req_buffer
+        .seek(0)
+        .and_then(|_| header.read(req_buffer))
+        .is_err()
+    {
+        return Err(error);
+    }
+    if header.response {
+        warn!("Dropping malformed response from {}: {:#}", src, error);
+        return Ok(Vec::new());
+    }
+    info!("Malformed query from {}: {:#}", src, error);
+
+    let mut packet = DnsPacket::new();
+    packet.header.id = header.id;
+    packet.header.opcode = header.opcode;
+    packet.header.recursion_desired = header.recursion_desired;
+    packet.header.recursion_available = config.mode != Mode::Authoritative;
+    packet.header.response = true;
+    packet.header.rescode = ResultCode::FORMERR;
+    respond(&mut packet, src, MIN_UDP_PAYLOAD_SIZE, None)
+}
+
+/// Check a secondary zone right away when its primary says it changed
+/// (RFC 1996, section 3.7). The NOTIFY must come from one of the zone's
+/// primaries or be signed with its primary key.
+fn notified(
+    req: &mut DnsPacket,
+    packet: &mut DnsPacket,
+    src: SocketAddr,
+    limit: usize,
+    config: &Config,
+    zones: &Catalog,
+    signer: Option<&mut Signer>,
+) -> Result<Vec<BytePacketBuffer>> {
+    packet
+        .questions
+        .extend(req.questions.iter().take(1).cloned());
+    let question = match req.questions.as_slice() {
+        [question] if question.qtype == QueryType::SOA => question,
+        _ => {
+            info!("NOTIFY from {} without a single SOA question", src);
+            packet.header.rescode = ResultCode::FORMERR;
+            return respond(packet, src, limit, signer);
+        }
+    };
+
+    let zone = match config.zone(&question.name).filter(|z| z.is_secondary()) {
+        Some(zone) => zone,
+        None => {
+            info!(
+                "NOTIFY from {} for {}, not a secondary zone of ours",
+                src, question.name
+            );
+            packet.header.rescode = ResultCode::NOTAUTH;
+            let reason = format!("not a secondary for {}", question.name);
+            add_extended_error(packet, ede::NOT_AUTHORITATIVE, reason);
+            return respond(packet, src, limit, signer);
+        }
+    };
+    let key_name = signer.as_deref().and_then(Signer::key_name);
+    if !zone.allows_notify(src.ip(), key_name) {
+        info!("Refusing NOTIFY of {} from {}", question.name, src);
+        packet.header.rescode = ResultCode::REFUSED;
+        let reason = format!("{} is not a primary for {}", src.ip(), question.name);
+        add_extended_error(packet, ede::PROHIBITED, reason);
+        return respond(packet, src, limit, signer);
+    }
+
+    info!("Received NOTIFY of zone {} from {}", question.name, src);
+    if let Some(wakeup) = zones.wakeup(&zone.name) {
+        wakeup.wake();
+    }
+    packet.header.authoritative_answer = true;
+    respond(packet, src, limit, signer)
+}
+
+/// Change a zone we are the primary for as an UPDATE asks (RFC 2136,
+/// section 3). The UPDATE must be signed with one of the zone's update
+/// keys.
+fn updated(
+    req: &mut DnsPacket,
+    packet: &mut DnsPacket,
+    src: SocketAddr,
+    limit: usize,
+    config: &Config,
+    zones: &Catalog,
+    signer: Option<&mut Signer>,
+) -> Result<Vec<BytePacketBuffer>> {
+    // the zone section takes the place of the question
+    packet
+        .questions
+        .extend(req.questions.iter().take(1).cloned());
+    let zone_name = match req.questions.as_slice() {
+        [zone] if zone.qtype == QueryType::SOA && zone.class == DnsClass::IN => &zone.name,
+        _ => {
+            info!("UPDATE from {} without a single zone", src);
+            packet.header.rescode = ResultCode::FORMERR;
+            return respond(packet, src, limit, signer);
+        }
+    };
+
+    let zone = config
+        .zone(zone_name)
+        .filter(|z| !z.is_secondary() && zones.get(&z.name).is_some());
+    let zone = match zone {
+        Some(zone) => zone,
+        None => {
+            info!("UPDATE from {} for {}, not a zone of ours", src, zone_name);
+            packet.header.rescode = ResultCode::NOTAUTH;
+            let reason = format!("not the primary for {}", zone_name);
+            add_extended_error(packet, ede::NOT_AUTHORITATIVE, reason);
+            return respond(packet, src, limit, signer);
+        }
+    };
+    let key_name = signer.as_deref().and_then(Signer::key_name);
+    if !zone.allows_update(key_name) {
+        info!("Refusing UPDATE of {} from {}", zone_name, src);
+        packet.header.rescode = ResultCode::REFUSED;
+        let reason = format!("{} may not update {}", src.ip(), zone_name);
+        add_extended_error(packet, ede::PROHIBITED, reason);
+        return respond(packet, src, limit, signer);
+    }
+
+    packet.header.rescode =
+        match update::update(zones, config, zone, &req.answers, &req.authorities) {
+            Ok(rescode) => rescode,
+            Err(e) => {
+                warn!("cannot update zone {}: {:#}", zone_name, e);
+                ResultCode::SERVFAIL
+            }
+        };
+    info!(
+        "UPDATE of zone {} from {} with key {}: {:?}",
+        zone_name,
+        src,
+        key_name.unwrap_or_default(),
+        packet.header.rescode
+    );
+    respond(packet, src, limit, signer)
+}
+
+/// Send a zone over TCP in as few messages as it fits in (RFC 5936,
+/// section 2.2): all of it, or with the `serial` of an IXFR only what
+/// changed since. Over UDP an IXFR only gets the current SOA, telling the
+/// secondary to come back over TCP if it is behind (RFC 1995, section 2).
+fn transfer(
+    packet: &mut DnsPacket,
+    src: SocketAddr,
+    transport: Transport,
+    config: &Config,
+    zones: &Catalog,
+    mut signer: Option<&mut Signer>,
+    serial: Option<u32>,
+) -> Result<Vec<BytePacketBuffer>> {
+    let limit = match transport {
+        Transport::Tcp => u16::MAX as usize,
+        Transport::Udp => MIN_UDP_PAYLOAD_SIZE,
+    };
+    let qname = packet.questions[0].name.clone();
+
+    let zone = match zones.find(&qname).filter(|z| name::eq(&z.origin, &qname)) {
+        Some(zone) => zone,
+        None => {
+            info!("Refusing zone transfer of {}, not a zone of ours", qname);
+            packet.header.rescode = ResultCode::NOTAUTH;
+            let reason = format!("not authoritative for {}", qname);
+            add_extended_error(packet, ede::NOT_AUTHORITATIVE, reason);
+            return respond(packet, src, limit, signer);
+        }
+    };
+    let key_name = signer.as_deref().and_then(Signer::key_name);
+    let allowed = config
+        .zone(&zone.origin)
+        .is_some_and(|z| z.allows_transfer(src.ip(), key_name));
+    if !allowed {
+        info!("Refusing zone transfer of {} to {}", qname, src);
+        packet.header.rescode = ResultCode::REFUSED;
+        let reason = format!("{} may not transfer {}", src.ip(), qname);
+        add_extended_error(packet, ede::PROHIBITED, reason);
+        return respond(packet, src, limit, signer);
+    }
+
+    let records = match transport {
+        Transport::Tcp => zone.transfer(serial),
+        Transport::Udp => vec![zone.soa()],
+    };
+    info!(
+        "Transferring zone {} with serial {} to {} in {} records",
+        name::or_root(&zone.origin),
+        zone.serial(),
+        src,
+        records.len()
+    );
+    packet.header.authoritative_answer = true;
+    let reserve = signer.as_deref().map_or(0, Signer::size);
+
+    let mut messages = Vec::new();
+    let mut buffer = BytePacketBuffer::new();
+    packet.write(&mut buffer)?;
+    let mut size = buffer.pos();
+    for rec in records {
+        // names are never compressed, so a record takes the same room
+        // wherever it goes
+        buffer.clear();
+        let len = rec.write(&mut buffer)?;
+        if size + len + reserve > limit && !packet.answers.is_empty() {
+            messages.push(seal(packet, signer.as_deref_mut())?);
+            // only the first message repeats the question
+            packet.questions.clear();
+            packet.edns = None;
+            packet.answers.clear();
+            size = 12;
+        }
+        packet.answers.push((*rec).clone());
+        size += len;
     }
+    messages.push(seal(packet, signer)?);
 
+    Ok(messages)
+}
+
+/// Write out one message of a response, signed if the request was.
+fn seal(packet: &mut DnsPacket, signer: Option<&mut Signer>) -> Result<BytePacketBuffer> {
+    let mut buffer = BytePacketBuffer::new();
+    packet.write(&mut buffer)?;
+    if let Some(signer) = signer {
+        signer.sign(&mut buffer)?;
+    }
+    Ok(buffer)
+}
+
+/// How the responses to a signed request are signed (RFC 8945, section
+/// 5.3).
+enum Signer<'a> {
+    /// With the key of the request, each message covering the MAC of the
+    /// one before it.
+    Key {
+        key: tsig::Key<'a>,
+        prior_mac: Vec<u8>,
+        error: ResultCode,
+        messages: usize,
+    },
+    /// The request's signature could not be checked, so the response
+    /// carries the error in a TSIG record without a MAC.
+    Unsigned(Tsig),
+}
+
+impl<'a> Signer<'a> {
+    fn new(key: tsig::Key<'a>, request: &Tsig, error: ResultCode) -> Signer<'a> {
+        Signer::Key {
+            key,
+            prior_mac: request.mac.clone(),
+            error,
+            messages: 0,
+        }
+    }
+
+    /// The key the request was signed with, if its signature holds.
+    fn key_name(&self) -> Option<&str> {
+        match self {
+            Signer::Key {
+                key,
+                error: ResultCode::NOERROR,
+                ..
+            } => Some(key.name),
+            _ => None,
+        }
+    }
+
+    /// Room to leave in a message for the signature.
+    fn size(&self) -> usize {
+        match self {
+            Signer::Key { key, .. } => Tsig::size(key.name),
+            Signer::Unsigned(tsig) => Tsig::size(&tsig.key_name),
+        }
+    }
+
+    /// Sign the message written to `buffer`.
+    fn sign(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        match self {
+            Signer::Key {
+                key,
+                prior_mac,
+                error,
+                messages,
+            } => {
+                // all but the first message of a transfer sign the timers only
+                let tsig = Tsig::sign(buffer, key, Some(prior_mac), *messages > 0, *error)?;
+                tsig.write(buffer)?;
+                *prior_mac = tsig.mac;
+                *messages += 1;
+            }
+            Signer::Unsigned(tsig) => tsig.write(buffer)?,
+        }
+        Ok(())
+    }
+}
+
+/// Write out `packet`, trimmed to the `limit` the client can take, and sign
+/// it if the request was signed.
+fn respond(
+    packet: &mut DnsPacket,
+    src: SocketAddr,
+    limit: usize,
+    signer: Option<&mut Signer>,
+) -> Result<Vec<BytePacketBuffer>> {
+    let reserve = signer.as_deref().map_or(0, Signer::size);
     let mut res_buffer = BytePacketBuffer::new();
-    packet.write(&mut res_buffer)?;
+    packet.write_within(&mut res_buffer, limit - reserve)?;
 
-    let len = res_buffer.pos();
-    let data = res_buffer.get_range(0, len)?;
+    if packet.header.truncated_message {
+        info!(
+            "Answer to {} does not fit in {} bytes, truncated",
+            src, limit
+        );
+    }
+    if let Some(signer) = signer {
+        signer.sign(&mut res_buffer)?;
+    }
 
-    socket.send_to(data, src)?;
+    Ok(vec![res_buffer])
+}
 
-    Ok(())
+/// Find the answer to a query the way the configured mode asks for.
+fn resolve(
+    config: &Config,
+    qname: &str,
+    qtype: QueryType,
+    client_subnet: Option<&ClientSubnet>,
+) -> Result<DnsPacket> {
+    match config.mode {
+        Mode::Recursive => recursive_lookup(config, qname, qtype, client_subnet),
+        Mode::Forward => forward_lookup(config, qname, qtype, client_subnet),
+        // answered from the zones before ever getting here
+        Mode::Authoritative => bail!("an authoritative server does not resolve {}", qname),
+    }
 }
 
-pub fn recursive_lookup(qname: &str, qtype: QueryType) -> Result<DnsPacket> {
-    let mut ns = ROOT_SERVER.parse().unwrap();
+/// Pass the query on to the configured upstream servers, trying them in
+/// turn until one of them answers.
+pub fn forward_lookup(
+    config: &Config,
+    qname: &str,
+    qtype: QueryType,
+    client_subnet: Option<&ClientSubnet>,
+) -> Result<DnsPacket> {
+    let mut last_error = anyhow!("no upstream servers configured");
+    for server in &config.upstreams {
+        match lookup(
+            qname,
+            qtype,
+            *server,
+            client_subnet,
+            config.timeouts.upstream,
+        ) {
+            Ok(res) => return Ok(res),
+            Err(e) => {
+                warn!("upstream {} failed: {}", server, e);
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// How many DNAME redirections we follow before giving up on a query.
+const MAX_DNAME_CHAIN: usize = 8;
+
+/// One query made on the way down from the root.
+#[derive(Debug)]
+pub struct TraceHop {
+    pub qname: String,
+    pub qtype: QueryType,
+    pub server: SocketAddr,
+    pub latency: Duration,
+    /// The server's answer, or why there was none.
+    pub response: Result<DnsPacket, String>,
+    /// NS records of the referral the server answered with, if any.
+    pub referral: Vec<DnsRecord>,
+    /// Addresses for those nameservers that came along in the referral.
+    pub glue: Vec<DnsRecord>,
+}
+
+impl TraceHop {
+    fn new(
+        qname: &str,
+        qtype: QueryType,
+        server: SocketAddr,
+        latency: Duration,
+        response: &Result<DnsPacket>,
+    ) -> TraceHop {
+        let (referral, glue) = match response {
+            Ok(res) if res.answers.is_empty() && res.header.rescode == ResultCode::NOERROR => {
+                let referral: Vec<_> = res
+                    .authorities
+                    .iter()
+                    .filter(|rec| {
+                        matches!(rec, DnsRecord::NS { domain, .. } if name::is_subdomain(qname, domain))
+                    })
+                    .cloned()
+                    .collect();
+                let glue = res
+                    .resources
+                    .iter()
+                    .filter(|rec| matches!(rec, DnsRecord::A { .. } | DnsRecord::AAAA { .. }))
+                    .filter(|rec| {
+                        referral.iter().any(|ns| {
+                            matches!(ns, DnsRecord::NS { host, .. } if name::eq(host, rec.domain()))
+                        })
+                    })
+                    .cloned()
+                    .collect();
+                (referral, glue)
+            }
+            _ => (Vec::new(), Vec::new()),
+        };
+
+        TraceHop {
+            qname: qname.to_string(),
+            qtype,
+            server,
+            latency,
+            response: match response {
+                Ok(res) => Ok(res.clone()),
+                Err(e) => Err(e.to_string()),
+            },
+            referral,
+            glue,
+        }
+    }
+}
+
+/// The path [`trace_lookup`] took through the delegations, and where it
+/// ended.
+#[derive(Debug)]
+pub struct Trace {
+    /// Every query in the order they were made, including those for the
+    /// addresses of nameservers that came without glue.
+    pub hops: Vec<TraceHop>,
+    pub result: Result<DnsPacket>,
+}
+
+/// Resolve `qname` from the root down like [`recursive_lookup`], keeping a
+/// record of every server asked along the way.
+pub fn trace_lookup(config: &Config, qname: &str, qtype: QueryType) -> Trace {
+    let mut hops = Vec::new();
+    let result = lookup_following_dname(config, qname, qtype, None, 0, Some(&mut hops));
+    Trace { hops, result }
+}
+
+/// Resolve `qname` from the root down. If `client_subnet` is given, it is
+/// passed to those of the servers along the way that the configuration
+/// lists, so they can tailor their answers.
+pub fn recursive_lookup(
+    config: &Config,
+    qname: &str,
+    qtype: QueryType,
+    client_subnet: Option<&ClientSubnet>,
+) -> Result<DnsPacket> {
+    lookup_following_dname(config, qname, qtype, client_subnet, 0, None)
+}
+
+/// Resolve `qname`, and if the answer redirects it through a DNAME into a
+/// name the upstream didn't answer for, resolve that name as well.
+fn lookup_following_dname(
+    config: &Config,
+    qname: &str,
+    qtype: QueryType,
+    client_subnet: Option<&ClientSubnet>,
+    depth: usize,
+    mut hops: Option<&mut Vec<TraceHop>>,
+) -> Result<DnsPacket> {
+    let mut res = iterative_lookup(config, qname, qtype, client_subnet, hops.as_deref_mut())?;
+
+    let target = match res.apply_dname(qname)? {
+        Some(target) => target,
+        None => return Ok(res),
+    };
+    if depth >= MAX_DNAME_CHAIN {
+        bail!(
+            "DNAME chain for {} is longer than {}",
+            qname,
+            MAX_DNAME_CHAIN
+        );
+    }
+
+    info!("following DNAME from {} to {}", qname, target);
+    let chased = lookup_following_dname(config, &target, qtype, client_subnet, depth + 1, hops)?;
+    res.header.rescode = chased.header.rescode;
+    res.answers.extend(chased.answers);
+    res.authorities = chased.authorities;
+    res.resources = chased.resources;
+
+    Ok(res)
+}
+
+fn iterative_lookup(
+    config: &Config,
+    qname: &str,
+    qtype: QueryType,
+    client_subnet: Option<&ClientSubnet>,
+    mut hops: Option<&mut Vec<TraceHop>>,
+) -> Result<DnsPacket> {
+    let mut server = *config
+        .root_servers
+        .choose(&mut rand::thread_rng())
+        .ok_or_else(|| anyhow!("no root servers configured"))?;
 
     loop {
-        println!(
+        info!(
             "attempting lookup on {}'s {:?} record with nameserver {}",
-            qname, qtype, ns
+            qname, qtype, server
         );
 
-        let server = (ns, 53);
-        let res = lookup(qname, qtype, server)?;
+        let started = Instant::now();
+        let subnet = client_subnet.filter(|_| config.client_subnet.servers.contains(&server.ip()));
+        let res = lookup(qname, qtype, server, subnet, config.timeouts.upstream);
+        if let Some(hops) = hops.as_deref_mut() {
+            hops.push(TraceHop::new(qname, qtype, server, started.elapsed(), &res));
+        }
+        let res = res?;
 
         if !res.answers.is_empty() && res.header.rescode == ResultCode::NOERROR {
             return Ok(res);
@@ -115,7 +1205,7 @@ pub fn recursive_lookup(qname: &str, qtype: QueryType) -> Result<DnsPacket> {
             return Ok(res);
         }
         if let Some(new_ns) = res.get_resolved_ns(qname) {
-            ns = new_ns;
+            server = SocketAddr::new(new_ns, 53);
             continue;
         }
 
@@ -124,12 +1214,22 @@ pub fn recursive_lookup(qname: &str, qtype: QueryType) -> Result<DnsPacket> {
             None => return Ok(res),
         };
 
-        let recursive_response = recursive_lookup(new_ns_name, QueryType::A)?;
+        let no_authority = |reason| ResolveError::new(ede::NO_REACHABLE_AUTHORITY, reason);
+        let recursive_response = lookup_following_dname(
+            config,
+            new_ns_name,
+            QueryType::A,
+            None,
+            0,
+            hops.as_deref_mut(),
+        )
+        .map_err(|e| no_authority(format!("cannot resolve nameserver {}: {}", new_ns_name, e)))?;
 
         if let Some(new_ns) = recursive_response.pick_one_server() {
-            ns = new_ns;
+            server = SocketAddr::new(new_ns, 53);
         } else {
-            return Ok(res);
+            let reason = format!("no address for nameserver {}", new_ns_name);
+            return Err(no_authority(reason).into());
         }
     }
 }
@@ -0,0 +1,224 @@
+//! The history of a zone's changes, from which secondaries are sent only
+//! what changed since the version they have (RFC 1995).
+//!
+//! The journal is saved next to the zone's master file, with `.jnl` added
+//! to its name, in the order IXFR sends the changes: for each version, the
+//! old SOA and the records deleted, then the new SOA and the records added.
+
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::packet::{name, qtype::QueryType, record::DnsRecord};
+
+use super::{master, Diff, Zone};
+
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    diffs: VecDeque<Diff>,
+}
+
+/// Where the journal of the zone in the master file at `zone_file` is kept.
+pub fn path(zone_file: &Path) -> PathBuf {
+    let mut path = zone_file.as_os_str().to_owned();
+    path.push(".jnl");
+    path.into()
+}
+
+/// The changes from the version `old` of a zone to the version `new`.
+pub fn diff(old: &Zone, new: &Zone) -> Diff {
+    let old_records: HashSet<&DnsRecord> = old.records().collect();
+    let new_records: HashSet<&DnsRecord> = new.records().collect();
+    let is_data = |rec: &&DnsRecord| rec.qtype() != QueryType::SOA;
+
+    let deleted = old
+        .records()
+        .filter(is_data)
+        .filter(|rec| !new_records.contains(rec));
+    let added = new
+        .records()
+        .filter(is_data)
+        .filter(|rec| !old_records.contains(rec));
+    Diff {
+        deleted: std::iter::once(old.soa()).chain(deleted).cloned().collect(),
+        added: std::iter::once(new.soa()).chain(added).cloned().collect(),
+    }
+}
+
+impl Journal {
+    /// Read the journal at `path` of the zone at `origin`, which must lead
+    /// to the version with `serial`. There is none if the file is missing.
+    pub fn load(path: &Path, origin: &str, serial: u32) -> Result<Journal> {
+        if !path.exists() {
+            return Ok(Journal::default());
+        }
+
+        let is_data = |rec: &DnsRecord| rec.qtype() != QueryType::SOA;
+        let mut records = master::read(path, origin)?.into_iter().peekable();
+        let mut journal = Journal::default();
+        while let Some(old) = records.next() {
+            let mut diff = Diff {
+                deleted: vec![old],
+                added: Vec::new(),
+            };
+            while let Some(rec) = records.next_if(is_data) {
+                diff.deleted.push(rec);
+            }
+            diff.added.extend(records.next());
+            while let Some(rec) = records.next_if(is_data) {
+                diff.added.push(rec);
+            }
+
+            let (from, to) = match (diff.from(), diff.to()) {
+                (Some(from), Some(to)) => (from, to),
+                _ => bail!("{}: changes not between two SOA records", path.display()),
+            };
+            if let Some(last) = journal.last_serial() {
+                if from != last {
+                    bail!(
+                        "{}: changes from serial {} follow changes to serial {}",
+                        path.display(),
+                        from,
+                        last
+                    );
+                }
+            }
+            if from == to {
+                bail!("{}: changes keep serial {}", path.display(), from);
+            }
+            journal.diffs.push_back(diff);
+        }
+
+        match journal.last_serial() {
+            Some(last) if last != serial => bail!(
+                "{}: changes lead to serial {}, but the zone has serial {}",
+                path.display(),
+                last,
+                serial
+            ),
+            _ => Ok(journal),
+        }
+    }
+
+    /// Save the journal of the zone at `origin` to `path`.
+    pub fn save(&self, path: &Path, origin: &str) -> Result<()> {
+        let comment = format!("changes to zone {}", name::or_root(origin));
+        let records = self
+            .diffs
+            .iter()
+            .flat_map(|diff| diff.deleted.iter().chain(&diff.added));
+        master::write_records(path, &comment, records)
+    }
+
+    /// The serial the latest changes lead to.
+    fn last_serial(&self) -> Option<u32> {
+        self.diffs.back().and_then(Diff::to)
+    }
+
+    /// Add the changes leading to the latest version of a zone with
+    /// `zone_size` records. The oldest are forgotten once there are more
+    /// records in the journal than in the zone, as sending all of it is
+    /// cheaper then.
+    pub fn push(&mut self, diff: Diff, zone_size: usize) {
+        let size = |diff: &Diff| diff.deleted.len() + diff.added.len();
+        self.diffs.push_back(diff);
+        let mut total: usize = self.diffs.iter().map(size).sum();
+        while total > zone_size {
+            match self.diffs.pop_front() {
+                Some(oldest) => total -= size(&oldest),
+                None => break,
+            }
+        }
+    }
+
+    /// The changes from the version with `serial` to the latest, `None` if
+    /// the journal does not go back that far.
+    pub fn since(&self, serial: u32) -> Option<impl Iterator<Item = &Diff>> {
+        let start = self.diffs.iter().position(|d| d.from() == Some(serial))?;
+        Some(self.diffs.range(start..))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::packet::class::DnsClass;
+
+    /// Version `serial` of a zone, with an address for each of `hosts` and
+    /// a few more that never change.
+    fn zone(serial: u32, hosts: &[u8]) -> Zone {
+        let mut records = vec![
+            DnsRecord::SOA {
+                domain: "example.com.".to_string(),
+                class: DnsClass::IN,
+                ttl: 3600,
+                mname: "ns.example.com.".to_string(),
+                rname: "hostmaster.example.com.".to_string(),
+                serial,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            },
+            DnsRecord::NS {
+                domain: "example.com.".to_string(),
+                class: DnsClass::IN,
+                host: "ns.example.com.".to_string(),
+                ttl: 3600,
+            },
+        ];
+        for &host in hosts.iter().chain(&[100, 101, 102, 103, 104]) {
+            records.push(DnsRecord::A {
+                domain: format!("h{}.example.com.", host),
+                class: DnsClass::IN,
+                addr: Ipv4Addr::new(192, 0, 2, host),
+                ttl: 300,
+            });
+        }
+        Zone::new("example.com.", records).unwrap()
+    }
+
+    fn serials<'a>(diffs: impl Iterator<Item = &'a Diff>) -> Vec<(u32, u32)> {
+        diffs
+            .map(|diff| (diff.from().unwrap(), diff.to().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn tells_the_changes_since_a_serial() {
+        let v1 = zone(1, &[1, 2]);
+        let mut v2 = zone(2, &[1, 3]);
+        v2.follow(&v1);
+        let mut v3 = zone(3, &[1, 3, 4]);
+        v3.follow(&v2);
+
+        let journal = &v3.journal;
+        assert_eq!(serials(journal.since(1).unwrap()), [(1, 2), (2, 3)]);
+        assert_eq!(serials(journal.since(2).unwrap()), [(2, 3)]);
+        assert!(journal.since(3).is_none());
+        assert!(journal.since(7).is_none());
+
+        let diff = journal.since(1).unwrap().next().unwrap();
+        assert_eq!(diff.deleted.len(), 2);
+        assert_eq!(diff.deleted[1].domain(), "h2.example.com.");
+        assert_eq!(diff.added.len(), 2);
+        assert_eq!(diff.added[1].domain(), "h3.example.com.");
+    }
+
+    #[test]
+    fn forgets_serials_once_larger_than_the_zone() {
+        let mut journal = Journal::default();
+        let mut previous = zone(1, &[1]);
+        for serial in 2..=4 {
+            let next = zone(serial, &[serial as u8]);
+            journal.push(diff(&previous, &next), 8);
+            previous = next;
+        }
+
+        assert!(journal.since(1).is_none());
+        assert_eq!(serials(journal.since(2).unwrap()), [(2, 3), (3, 4)]);
+    }
+}
@@ -0,0 +1,342 @@
+//! Reading zones from master files (RFC 1035, section 5).
+//!
+//! Besides records, the `$ORIGIN`, `$TTL` (RFC 2308) and `$INCLUDE`
+//! directives are understood. Entries may span lines inside parentheses,
+//! owners may be left blank to repeat the previous one, and TTL and class
+//! may be given in either order or left out.
+//!
+//! Zones are written back with one record per line and every name fully
+//! qualified.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::packet::{
+    class::DnsClass,
+    encoding::{parse_ttl, tokenize, Token},
+    name,
+    qtype::QueryType,
+    record::DnsRecord,
+};
+
+use super::Zone;
+
+/// How deeply `$INCLUDE`s may nest, so a file including itself fails
+/// instead of recursing forever.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// One entry of a master file, which may have spanned several lines.
+struct Entry {
+    line: usize,
+    /// Whether the entry starts with blank space, leaving out the owner.
+    blank_owner: bool,
+    text: String,
+}
+
+/// What carries over from one entry to the next.
+struct State {
+    origin: String,
+    default_ttl: Option<u32>,
+    last_owner: Option<String>,
+    last_ttl: Option<u32>,
+    last_class: DnsClass,
+}
+
+/// Read the records of the master file at `path`, taking relative names
+/// relative to `origin`.
+pub fn read(path: &Path, origin: &str) -> Result<Vec<DnsRecord>> {
+    let mut state = State {
+        origin: name::from_text(origin, "")?,
+        default_ttl: None,
+        last_owner: None,
+        last_ttl: None,
+        last_class: DnsClass::IN,
+    };
+    let mut records = Vec::new();
+    read_file(path, &mut state, &mut records, 0)?;
+    Ok(records)
+}
+
+/// Save `zone` to the master file at `path`, replacing it only once it is
+/// completely written.
+pub fn write(path: &Path, zone: &Zone) -> Result<()> {
+    let comment = format!(
+        "zone {}, serial {}",
+        name::or_root(&zone.origin),
+        zone.serial()
+    );
+    let soa = zone.soa();
+    let records = std::iter::once(soa).chain(zone.records().filter(|r| *r != soa));
+    write_records(path, &comment, records)
+}
+
+/// Save `records` to the master file at `path` in the order given, after a
+/// comment line.
+pub fn write_records<'a>(
+    path: &Path,
+    comment: &str,
+    records: impl Iterator<Item = &'a DnsRecord>,
+) -> Result<()> {
+    let mut text = format!("; {}\n", comment);
+    for rec in records {
+        let _ = writeln!(text, "{}", rec);
+    }
+
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    fs::write(&partial, text).with_context(|| format!("cannot write {:?}", partial))?;
+    fs::rename(&partial, path).with_context(|| format!("cannot replace {}", path.display()))
+}
+
+fn read_file(
+    path: &Path,
+    state: &mut State,
+    records: &mut Vec<DnsRecord>,
+    depth: usize,
+) -> Result<()> {
+    if depth > MAX_INCLUDE_DEPTH {
+        bail!("{}: $INCLUDE nested too deeply", path.display());
+    }
+    let text =
+        fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+
+    for entry in entries(path, &text)? {
+        let line = entry.line;
+        read_entry(path, entry, state, records, depth)
+            .with_context(|| format!("{}:{}", path.display(), line))?;
+    }
+    Ok(())
+}
+
+/// Split a master file into its entries, joining the lines of entries that
+/// continue inside parentheses.
+fn entries(path: &Path, text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut current: Option<Entry> = None;
+    let mut depth = 0usize;
+
+    for (i, line) in text.lines().enumerate() {
+        let entry = current.get_or_insert_with(|| Entry {
+            line: i + 1,
+            blank_owner: line.starts_with([' ', '\t']),
+            text: String::new(),
+        });
+        entry.text.push_str(line);
+        entry.text.push('\n');
+
+        // only parentheses outside of quotes and comments count
+        let mut quoted = false;
+        let mut escaped = false;
+        for c in line.chars() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = !quoted,
+                ';' if !quoted => break,
+                '(' if !quoted => depth += 1,
+                ')' if !quoted => {
+                    depth = depth
+                        .checked_sub(1)
+                        .ok_or_else(|| anyhow!("{}:{}: unbalanced ')'", path.display(), i + 1))?;
+                }
+                _ => {}
+            }
+        }
+        if depth == 0 {
+            entries.extend(current.take());
+        }
+    }
+
+    if let Some(entry) = current {
+        bail!("{}:{}: '(' is never closed", path.display(), entry.line);
+    }
+    Ok(entries)
+}
+
+fn read_entry(
+    path: &Path,
+    entry: Entry,
+    state: &mut State,
+    records: &mut Vec<DnsRecord>,
+    depth: usize,
+) -> Result<()> {
+    let tokens = tokenize(&entry.text)?;
+    let mut tokens = tokens.iter().peekable();
+
+    let first = match tokens.peek() {
+        Some(first) => first.text.as_str(),
+        None => return Ok(()),
+    };
+    if !entry.blank_owner && first.starts_with('$') {
+        let directive = first.to_ascii_uppercase();
+        tokens.next();
+        let args: Vec<&Token> = tokens.collect();
+        return match (directive.as_str(), args.as_slice()) {
+            ("$ORIGIN", [origin]) => {
+                state.origin = name::from_text(&origin.text, &state.origin)?;
+                Ok(())
+            }
+            ("$TTL", [ttl]) => {
+                state.default_ttl = Some(parse_ttl(&ttl.text)?);
+                Ok(())
+            }
+            ("$INCLUDE", [file, rest @ ..]) if rest.len() <= 1 => {
+                let file = path.parent().unwrap_or(Path::new(".")).join(&file.text);
+                // the origin changes for the included file only
+                let saved_origin = state.origin.clone();
+                if let Some(origin) = rest.first() {
+                    state.origin = name::from_text(&origin.text, &state.origin)?;
+                }
+                read_file(&file, state, records, depth + 1)?;
+                state.origin = saved_origin;
+                Ok(())
+            }
+            ("$ORIGIN" | "$TTL" | "$INCLUDE", _) => {
+                bail!("wrong number of arguments to {}", directive)
+            }
+            _ => bail!("unknown directive {}", first),
+        };
+    }
+
+    let owner = if entry.blank_owner {
+        state
+            .last_owner
+            .clone()
+            .ok_or_else(|| anyhow!("no owner given and no previous one to repeat"))?
+    } else {
+        let owner = tokens.next().map(|t| t.text.as_str()).unwrap_or_default();
+        name::from_text(owner, &state.origin)?
+    };
+
+    let mut ttl = None;
+    let mut class = None;
+    let qtype = loop {
+        let token = tokens
+            .next()
+            .ok_or_else(|| anyhow!("missing record type"))?;
+        if ttl.is_none() && token.text.starts_with(|c: char| c.is_ascii_digit()) {
+            ttl = Some(parse_ttl(&token.text)?);
+        } else if let (None, Ok(parsed)) = (class, token.text.parse::<DnsClass>()) {
+            class = Some(parsed);
+        } else {
+            break token.text.parse::<QueryType>()?;
+        }
+    };
+
+    let class = class.unwrap_or(state.last_class);
+    if class != DnsClass::IN {
+        bail!("only class IN is supported, not {}", class);
+    }
+    let rdata: Vec<Token> = tokens.cloned().collect();
+    let ttl = match ttl.or(state.default_ttl).or(state.last_ttl) {
+        Some(ttl) => ttl,
+        // Before RFC 2308 the SOA minimum was the default TTL.
+        None if qtype == QueryType::SOA => parse_ttl(
+            &rdata
+                .get(6)
+                .ok_or_else(|| anyhow!("missing field in SOA rdata"))?
+                .text,
+        )?,
+        None => bail!("no TTL given and no $TTL to fall back on"),
+    };
+
+    let record = DnsRecord::from_rdata(owner.clone(), class, ttl, qtype, &rdata, &state.origin)?;
+    state.last_owner = Some(owner);
+    state.last_ttl = Some(ttl);
+    state.last_class = class;
+    records.push(record);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::buffer::BytePacketBuffer;
+
+    fn soa() -> DnsRecord {
+        DnsRecord::SOA {
+            domain: "example.com.".to_string(),
+            class: DnsClass::IN,
+            ttl: 3600,
+            mname: "ns.example.com.".to_string(),
+            rname: "hostmaster.example.com.".to_string(),
+            serial: 1,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 300,
+        }
+    }
+
+    fn ns() -> DnsRecord {
+        DnsRecord::NS {
+            domain: "example.com.".to_string(),
+            class: DnsClass::IN,
+            host: "ns.example.com.".to_string(),
+            ttl: 3600,
+        }
+    }
+
+    #[test]
+    fn keeps_the_rdata_of_unknown_types() {
+        let wire = [0x00, 0x01, 0x02, 0xfe];
+        let mut buffer = BytePacketBuffer::new();
+        buffer.write_qname("x.example.com.").unwrap();
+        buffer.write_u16(65280).unwrap();
+        buffer.write_u16(DnsClass::IN.to_num()).unwrap();
+        buffer.write_u32(300).unwrap();
+        buffer.write_u16(wire.len() as u16).unwrap();
+        buffer.write_bytes(&wire).unwrap();
+        let sent = buffer.buf[..buffer.pos()].to_vec();
+
+        buffer.seek(0).unwrap();
+        let rec = DnsRecord::read(&mut buffer).unwrap();
+        assert_eq!(
+            rec.to_string(),
+            "x.example.com. 300 IN TYPE65280 \\# 4 000102FE"
+        );
+
+        let zone = Zone::new("example.com.", vec![soa(), ns(), rec]).unwrap();
+        let path = std::env::temp_dir().join(format!("unknown-{}.zone", std::process::id()));
+        write(&path, &zone).unwrap();
+        let records = read(&path, "example.com.").unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let rec = records
+            .iter()
+            .find(|r| r.qtype() == QueryType::UNKNOWN(65280))
+            .unwrap();
+        let mut buffer = BytePacketBuffer::new();
+        rec.write(&mut buffer).unwrap();
+        assert_eq!(buffer.buf[..buffer.pos()], sent[..]);
+    }
+
+    #[test]
+    fn reads_known_types_in_the_generic_form() {
+        let tokens = tokenize("\\# 4 C0000201").unwrap();
+        let rec = DnsRecord::from_rdata(
+            "a.example.com.".to_string(),
+            DnsClass::IN,
+            300,
+            QueryType::A,
+            &tokens,
+            "example.com.",
+        )
+        .unwrap();
+        assert_eq!(rec.to_string(), "a.example.com. 300 IN A 192.0.2.1");
+
+        let tokens = tokenize("\\# 3 C0000201").unwrap();
+        assert!(DnsRecord::from_rdata(
+            "a.example.com.".to_string(),
+            DnsClass::IN,
+            300,
+            QueryType::A,
+            &tokens,
+            "example.com.",
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,758 @@
+//! Zones we are authoritative for, and answering from them (RFC 1034,
+//! section 4.3.2).
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+
+use crate::config::ZoneConfig;
+use crate::packet::{name, qtype::QueryType, record::DnsRecord, rscode::ResultCode, DnsPacket};
+
+pub mod journal;
+pub mod master;
+pub mod notify;
+pub mod primary;
+pub mod secondary;
+pub mod transfer;
+pub mod update;
+
+use self::journal::Journal;
+use self::secondary::Wakeup;
+
+/// How many CNAMEs and DNAMEs are followed within a zone before giving up.
+const MAX_CHAIN: usize = 8;
+
+/// The labels of a name in reverse and lower case, so that the names below
+/// one sort right after it.
+type Key = Vec<String>;
+
+fn key(name: &str) -> Key {
+    name::labels(name)
+        .iter()
+        .rev()
+        .map(|l| l.to_ascii_lowercase())
+        .collect()
+}
+
+/// Whether serial `a` is newer than `b` in serial number arithmetic
+/// (RFC 1982), which lets serials wrap around.
+pub fn serial_gt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// The changes from one version of a zone to the next, as IXFR carries them
+/// (RFC 1995, section 4): the old SOA and the records deleted, then the new
+/// SOA and the records added.
+#[derive(Debug, Clone, Default)]
+pub struct Diff {
+    pub deleted: Vec<DnsRecord>,
+    pub added: Vec<DnsRecord>,
+}
+
+impl Diff {
+    /// The serial the changes start from.
+    pub fn from(&self) -> Option<u32> {
+        self.deleted.first().and_then(soa_serial)
+    }
+
+    /// The serial the changes lead to.
+    pub fn to(&self) -> Option<u32> {
+        self.added.first().and_then(soa_serial)
+    }
+}
+
+fn soa_serial(rec: &DnsRecord) -> Option<u32> {
+    match rec {
+        DnsRecord::SOA { serial, .. } => Some(*serial),
+        _ => None,
+    }
+}
+
+fn soa_timers(rec: &DnsRecord) -> Option<Timers> {
+    let secs = |s: u32| Duration::from_secs(s.max(1) as u64);
+    match rec {
+        DnsRecord::SOA {
+            refresh,
+            retry,
+            expire,
+            ..
+        } => Some(Timers {
+            refresh: secs(*refresh),
+            retry: secs(*retry),
+            expire: secs(*expire),
+        }),
+        _ => None,
+    }
+}
+
+/// Whether two records are the same but for their TTL.
+fn same_data(a: &DnsRecord, b: &DnsRecord) -> bool {
+    let mut b_like_a = b.clone();
+    b_like_a.set_ttl(a.ttl());
+    b_like_a.set_domain(a.domain().to_string());
+    name::eq(a.domain(), b.domain()) && *a == b_like_a
+}
+
+/// The records of one zone, indexed by owner name.
+pub struct Zone {
+    /// The apex, fully qualified.
+    pub origin: String,
+    nodes: BTreeMap<Key, Vec<DnsRecord>>,
+    /// The intervals of its SOA.
+    pub timers: Timers,
+    /// How the earlier versions we know of became this one.
+    pub journal: Journal,
+}
+
+/// The intervals of a zone's SOA, telling its secondaries how often to
+/// check for a new version and how long to serve it without one.
+#[derive(Debug, Clone, Copy)]
+pub struct Timers {
+    pub refresh: Duration,
+    pub retry: Duration,
+    pub expire: Duration,
+}
+
+/// Where a name falls within a zone.
+enum Position<'a> {
+    /// Below a zone cut, delegated to the nameservers at `cut`.
+    Delegated {
+        cut: &'a [DnsRecord],
+    },
+    /// Below the owner of a DNAME.
+    Redirected {
+        dname: &'a DnsRecord,
+    },
+    /// An owner of records.
+    Node {
+        records: &'a [DnsRecord],
+    },
+    /// A name that doesn't exist but is covered by a wildcard, whose
+    /// records are to be answered with.
+    Wildcard {
+        records: &'a [DnsRecord],
+    },
+    /// Owns no records, but names below it do.
+    EmptyNonTerminal,
+    NxDomain,
+}
+
+impl Zone {
+    /// Build a zone from its records, checking that it has exactly one SOA
+    /// and some nameservers at the apex, and no CNAME next to other data.
+    /// Records outside the zone are dropped with a warning.
+    pub fn new(origin: &str, records: Vec<DnsRecord>) -> Result<Zone> {
+        let origin = name::from_text(origin, "")?;
+        let mut nodes: BTreeMap<Key, Vec<DnsRecord>> = BTreeMap::new();
+
+        for rec in records {
+            if !name::is_subdomain(rec.domain(), &origin) {
+                warn!(
+                    "{}: ignoring out-of-zone record {}",
+                    name::or_root(&origin),
+                    rec
+                );
+                continue;
+            }
+            let node = nodes.entry(key(rec.domain())).or_default();
+            // the same record twice is the same record once
+            if !node.contains(&rec) {
+                node.push(rec);
+            }
+        }
+
+        let apex = nodes.get(&key(&origin)).map_or(&[][..], |r| r.as_slice());
+        let timers = match apex.iter().filter_map(soa_timers).collect::<Vec<_>>()[..] {
+            [timers] => timers,
+            [] => bail!("{}: no SOA record at the apex", name::or_root(&origin)),
+            _ => bail!("{}: more than one SOA record", name::or_root(&origin)),
+        };
+        if !apex.iter().any(|r| r.qtype() == QueryType::NS) {
+            bail!("{}: no NS records at the apex", name::or_root(&origin));
+        }
+        for records in nodes.values() {
+            let cnames = records.iter().filter(|r| r.qtype() == QueryType::CNAME);
+            if cnames.count() > 0 && records.len() > 1 {
+                bail!(
+                    "{}: a CNAME cannot stand next to other records",
+                    name::or_root(records[0].domain())
+                );
+            }
+        }
+
+        Ok(Zone {
+            origin,
+            nodes,
+            timers,
+            journal: Journal::default(),
+        })
+    }
+
+    /// Read a zone from its master file.
+    pub fn load(origin: &str, path: &Path) -> Result<Zone> {
+        let records = master::read(path, origin)?;
+        Zone::new(origin, records).with_context(|| path.display().to_string())
+    }
+
+    /// The SOA record at the apex.
+    pub fn soa(&self) -> &DnsRecord {
+        self.nodes[&key(&self.origin)]
+            .iter()
+            .find(|r| r.qtype() == QueryType::SOA)
+            .expect("a zone always has an SOA record")
+    }
+
+    pub fn serial(&self) -> u32 {
+        soa_serial(self.soa()).expect("the SOA record is an SOA record")
+    }
+
+    /// The zone after the changes of `diffs`, each starting from the serial
+    /// the one before leads to. They are added to its journal.
+    pub fn apply(&self, diffs: &[Diff]) -> Result<Zone> {
+        let mut records: Vec<DnsRecord> = self.records().cloned().collect();
+        let mut serial = self.serial();
+        for diff in diffs {
+            match (diff.from(), diff.to()) {
+                (Some(from), Some(to)) if from == serial => serial = to,
+                (Some(from), Some(_)) => bail!(
+                    "{}: changes from serial {} do not apply to serial {}",
+                    name::or_root(&self.origin),
+                    from,
+                    serial
+                ),
+                _ => bail!(
+                    "{}: changes without the SOA records they go between",
+                    name::or_root(&self.origin)
+                ),
+            }
+            for rec in &diff.deleted {
+                if let Some(i) = records.iter().position(|r| same_data(r, rec)) {
+                    records.swap_remove(i);
+                }
+            }
+            records.extend(diff.added.iter().cloned());
+        }
+
+        let mut zone = Zone::new(&self.origin, records)?;
+        zone.journal = self.journal.clone();
+        let size = zone.records().count();
+        for diff in diffs {
+            zone.journal.push(diff.clone(), size);
+        }
+        Ok(zone)
+    }
+
+    /// Take over the journal of the `previous` version of the zone, adding
+    /// the changes from it to this one. Without a newer serial the changes
+    /// cannot be told apart, and the journal starts over.
+    pub fn follow(&mut self, previous: &Zone) {
+        if !serial_gt(self.serial(), previous.serial()) {
+            self.journal = Journal::default();
+            return;
+        }
+        self.journal = previous.journal.clone();
+        let size = self.records().count();
+        self.journal.push(journal::diff(previous, self), size);
+    }
+
+    /// The records to send a secondary: the whole zone between two copies
+    /// of the SOA as AXFR does, or with the `serial` of the version the
+    /// secondary has, only what changed since as IXFR does (RFC 1995,
+    /// section 4) if the journal goes back that far.
+    pub fn transfer(&self, serial: Option<u32>) -> Vec<&DnsRecord> {
+        let soa = self.soa();
+        if let Some(serial) = serial {
+            if !serial_gt(self.serial(), serial) {
+                return vec![soa];
+            }
+            if let Some(diffs) = self.journal.since(serial) {
+                let mut records = vec![soa];
+                for diff in diffs {
+                    records.extend(&diff.deleted);
+                    records.extend(&diff.added);
+                }
+                records.push(soa);
+                return records;
+            }
+        }
+
+        let data = self.records().filter(|r| r.qtype() != QueryType::SOA);
+        std::iter::once(soa)
+            .chain(data)
+            .chain(std::iter::once(soa))
+            .collect()
+    }
+
+    /// Every record of the zone, in canonical order of their owners.
+    pub fn records(&self) -> impl Iterator<Item = &DnsRecord> {
+        self.nodes.values().flatten()
+    }
+
+    /// Find where `qname` falls, walking down from the apex so that zone
+    /// cuts and DNAMEs on the way are noticed.
+    fn position(&self, qname: &str) -> Position<'_> {
+        let qkey = key(qname);
+        let apex_len = key(&self.origin).len();
+
+        for len in apex_len..=qkey.len() {
+            let records = match self.nodes.get(&qkey[..len]) {
+                Some(records) => records,
+                None => continue,
+            };
+            if len > apex_len && records.iter().any(|r| r.qtype() == QueryType::NS) {
+                return Position::Delegated { cut: records };
+            }
+            if len < qkey.len() {
+                if let Some(dname) = records.iter().find(|r| r.qtype() == QueryType::DNAME) {
+                    return Position::Redirected { dname };
+                }
+            }
+        }
+
+        if let Some(records) = self.nodes.get(&qkey) {
+            return Position::Node { records };
+        }
+        if self.has_descendants(&qkey) {
+            return Position::EmptyNonTerminal;
+        }
+
+        // The name doesn't exist, but a wildcard just below its closest
+        // existing ancestor stands in for it (RFC 4592, section 3.3.1).
+        let closest_encloser = (apex_len..qkey.len())
+            .rev()
+            .map(|len| &qkey[..len])
+            .find(|ancestor| self.nodes.contains_key(*ancestor) || self.has_descendants(ancestor))
+            .unwrap_or(&qkey[..apex_len]);
+        let mut source = closest_encloser.to_vec();
+        source.push("*".to_string());
+        match self.nodes.get(&source) {
+            Some(records) => Position::Wildcard { records },
+            None => Position::NxDomain,
+        }
+    }
+
+    /// Whether any names below the one with `key` own records.
+    fn has_descendants(&self, key: &[String]) -> bool {
+        self.nodes
+            .range(key.to_vec()..)
+            .find(|(k, _)| k.as_slice() != key)
+            .is_some_and(|(k, _)| k.starts_with(key))
+    }
+
+    /// Answer a query for `qname`, which must lie within the zone. CNAMEs
+    /// and DNAMEs are followed as long as they stay inside it.
+    pub fn answer(&self, qname: &str, qtype: QueryType) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.header.authoritative_answer = true;
+        packet.header.rescode = ResultCode::NOERROR;
+
+        let mut current = qname.to_string();
+        for _ in 0..MAX_CHAIN {
+            let next = match self.position(&current) {
+                Position::Delegated { cut } => {
+                    // Only a referral if nothing was answered on the way.
+                    if packet.answers.is_empty() {
+                        packet.header.authoritative_answer = false;
+                        packet
+                            .authorities
+                            .extend(cut.iter().filter(|r| r.qtype() == QueryType::NS).cloned());
+                        self.add_addresses(&mut packet, cut);
+                    }
+                    return packet;
+                }
+                Position::Redirected { dname } => {
+                    packet.answers.push(dname.clone());
+                    match dname.synthesize_cname(&current) {
+                        Ok(Some(cname)) => {
+                            let target = cname_target(&cname);
+                            packet.answers.push(cname);
+                            target
+                        }
+                        Ok(None) => unreachable!("the DNAME owner is above the name"),
+                        Err(_) => {
+                            packet.header.rescode = ResultCode::YXDOMAIN;
+                            return packet;
+                        }
+                    }
+                }
+                Position::Node { records } => match self.answer_from(&mut packet, records, qtype) {
+                    Some(target) => target,
+                    None => return packet,
+                },
+                Position::Wildcard { records } => {
+                    // the records of the wildcard, as if they were owned by
+                    // the name asked for
+                    let synthesized: Vec<_> = records
+                        .iter()
+                        .map(|rec| {
+                            let mut rec = rec.clone();
+                            rec.set_domain(current.clone());
+                            rec
+                        })
+                        .collect();
+                    match self.answer_from(&mut packet, &synthesized, qtype) {
+                        Some(target) => target,
+                        None => return packet,
+                    }
+                }
+                Position::EmptyNonTerminal => {
+                    self.add_soa(&mut packet);
+                    return packet;
+                }
+                Position::NxDomain => {
+                    packet.header.rescode = ResultCode::NXDOMAIN;
+                    self.add_soa(&mut packet);
+                    return packet;
+                }
+            };
+
+            if !name::is_subdomain(&next, &self.origin) {
+                return packet;
+            }
+            current = next;
+        }
+
+        warn!("{}: CNAME chain from {} is too long", self.origin, qname);
+        packet
+    }
+
+    /// Answer from the records the name asked for owns: those of the type
+    /// asked for, else its CNAME, whose target is returned to be followed,
+    /// else no data.
+    fn answer_from(
+        &self,
+        packet: &mut DnsPacket,
+        records: &[DnsRecord],
+        qtype: QueryType,
+    ) -> Option<String> {
+        let matching: Vec<_> = records
+            .iter()
+            .filter(|r| qtype == QueryType::ANY || r.qtype() == qtype)
+            .cloned()
+            .collect();
+        if !matching.is_empty() {
+            self.add_addresses(packet, &matching);
+            packet.answers.extend(matching);
+            return None;
+        }
+
+        match records.iter().find(|r| r.qtype() == QueryType::CNAME) {
+            Some(cname) => {
+                packet.answers.push(cname.clone());
+                Some(cname_target(cname))
+            }
+            None => {
+                self.add_soa(packet);
+                None
+            }
+        }
+    }
+
+    /// The SOA for a negative answer, with the TTL negative answers may be
+    /// cached for (RFC 2308, section 5).
+    fn add_soa(&self, packet: &mut DnsPacket) {
+        let mut soa = self.soa().clone();
+        if let DnsRecord::SOA { minimum, ttl, .. } = &soa {
+            let negative_ttl = (*minimum).min(*ttl);
+            soa.set_ttl(negative_ttl);
+        }
+        packet.authorities.push(soa);
+    }
+
+    /// Add the addresses of the hosts `records` point to, as far as the
+    /// zone knows them: glue for referrals, and the hosts of NS, MX and
+    /// service bindings.
+    fn add_addresses(&self, packet: &mut DnsPacket, records: &[DnsRecord]) {
+        for rec in records {
+            let host = match rec {
+                DnsRecord::NS { host, .. } | DnsRecord::MX { host, .. } => host,
+                DnsRecord::SVCB { target, .. } | DnsRecord::HTTPS { target, .. } => target,
+                _ => continue,
+            };
+            let addresses = self.nodes.get(&key(host)).into_iter().flatten();
+            for addr in addresses {
+                let is_address = matches!(addr.qtype(), QueryType::A | QueryType::AAAA);
+                if is_address && !packet.resources.contains(addr) {
+                    packet.resources.push(addr.clone());
+                }
+            }
+        }
+    }
+}
+
+fn cname_target(cname: &DnsRecord) -> String {
+    match cname {
+        DnsRecord::CNAME { host, .. } => host.clone(),
+        _ => unreachable!(),
+    }
+}
+
+/// All the zones served, answering for the names within them. Secondary
+/// zones are swapped for new versions as they are transferred.
+#[derive(Default)]
+pub struct Catalog {
+    zones: RwLock<Vec<Slot>>,
+    /// Held while a new version of a zone is made from the one served.
+    changes: Mutex<()>,
+}
+
+/// A zone we serve, without records while a secondary has not been
+/// transferred yet or has expired.
+struct Slot {
+    origin: String,
+    zone: Option<Arc<Zone>>,
+    /// Wakes up the secondary's refresh on NOTIFY.
+    wakeup: Arc<Wakeup>,
+}
+
+impl Catalog {
+    /// Load the master file of every configured zone. A secondary starts
+    /// from the copy it saved last, if there is one.
+    pub fn load(zones: &[ZoneConfig]) -> Result<Catalog> {
+        let mut slots = Vec::new();
+        for config in zones {
+            let origin = name::from_text(&config.name, "")?;
+            let zone = match Zone::load(&config.name, &config.file) {
+                Ok(mut zone) => {
+                    let path = journal::path(&config.file);
+                    match Journal::load(&path, &zone.origin, zone.serial()) {
+                        Ok(journal) => zone.journal = journal,
+                        Err(e) => warn!("ignoring journal of zone {}: {:#}", config.name, e),
+                    }
+                    info!(
+                        "loaded zone {} with serial {} from {}",
+                        name::or_root(&zone.origin),
+                        zone.serial(),
+                        config.file.display()
+                    );
+                    Some(Arc::new(zone))
+                }
+                Err(e) if config.is_secondary() => {
+                    if config.file.exists() {
+                        warn!("ignoring saved copy of zone {}: {:#}", config.name, e);
+                    }
+                    info!("zone {} waits for its first transfer", config.name);
+                    None
+                }
+                Err(e) => return Err(e.context(format!("cannot load zone {}", config.name))),
+            };
+            slots.push(Slot {
+                origin,
+                zone,
+                wakeup: Arc::default(),
+            });
+        }
+        Ok(Catalog {
+            zones: RwLock::new(slots),
+            changes: Mutex::default(),
+        })
+    }
+
+    /// The zone `qname` belongs to, the one with the closest apex above it,
+    /// whose records may be missing.
+    fn lookup(&self, qname: &str) -> Option<(String, Option<Arc<Zone>>)> {
+        self.zones
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|slot| name::is_subdomain(qname, &slot.origin))
+            .max_by_key(|slot| name::labels(&slot.origin).len())
+            .map(|slot| (slot.origin.clone(), slot.zone.clone()))
+    }
+
+    /// The zone `qname` belongs to, if we have its records.
+    pub fn find(&self, qname: &str) -> Option<Arc<Zone>> {
+        self.lookup(qname).and_then(|(_, zone)| zone)
+    }
+
+    /// The zone with apex `origin`, if we have its records.
+    pub fn get(&self, origin: &str) -> Option<Arc<Zone>> {
+        self.zones
+            .read()
+            .unwrap()
+            .iter()
+            .find(|slot| name::eq(&slot.origin, origin))
+            .and_then(|slot| slot.zone.clone())
+    }
+
+    /// Serve `zone` instead of the version of it served so far.
+    pub fn replace(&self, zone: Zone) {
+        let mut zones = self.zones.write().unwrap();
+        match zones
+            .iter_mut()
+            .find(|slot| name::eq(&slot.origin, &zone.origin))
+        {
+            Some(slot) => slot.zone = Some(Arc::new(zone)),
+            None => zones.push(Slot {
+                origin: zone.origin.clone(),
+                zone: Some(Arc::new(zone)),
+                wakeup: Arc::default(),
+            }),
+        }
+    }
+
+    /// Make sure no one else changes a zone until the guard is dropped, so
+    /// that two changes made at once do not lose one of them.
+    pub fn lock_changes(&self) -> MutexGuard<'_, ()> {
+        self.changes.lock().unwrap()
+    }
+
+    /// What wakes up the refresh of the zone with apex `origin` early.
+    pub fn wakeup(&self, origin: &str) -> Option<Arc<Wakeup>> {
+        self.zones
+            .read()
+            .unwrap()
+            .iter()
+            .find(|slot| name::eq(&slot.origin, origin))
+            .map(|slot| slot.wakeup.clone())
+    }
+
+    /// Stop answering from the zone with apex `origin`, keeping it in the
+    /// catalog so that queries for it fail instead of being refused.
+    pub fn expire(&self, origin: &str) {
+        let mut zones = self.zones.write().unwrap();
+        if let Some(slot) = zones.iter_mut().find(|slot| name::eq(&slot.origin, origin)) {
+            slot.zone = None;
+        }
+    }
+
+    /// Answer from the zone `qname` belongs to, `None` if it belongs to
+    /// none of ours. A zone without records fails the query.
+    pub fn answer(&self, qname: &str, qtype: QueryType) -> Option<DnsPacket> {
+        let (origin, zone) = self.lookup(qname)?;
+        match zone {
+            Some(zone) => Some(zone.answer(qname, qtype)),
+            None => {
+                debug!("zone {} has no records to answer from", origin);
+                let mut packet = DnsPacket::new();
+                packet.header.rescode = ResultCode::SERVFAIL;
+                Some(packet)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone() -> Zone {
+        let records = [
+            "example.com. 3600 IN SOA ns.example.com. hostmaster.example.com. 1 3600 600 86400 300",
+            "example.com. 3600 IN NS ns.example.com.",
+            "ns.example.com. 300 IN A 192.0.2.53",
+            "www.example.com. 300 IN A 192.0.2.1",
+            "*.example.com. 300 IN A 192.0.2.10",
+            "a.b.c.example.com. 300 IN A 192.0.2.2",
+            "old.example.com. 300 IN DNAME new.example.com.",
+            "host.new.example.com. 300 IN A 192.0.2.3",
+            "sub.example.com. 300 IN NS ns.sub.example.com.",
+            "ns.sub.example.com. 300 IN A 192.0.2.54",
+        ];
+        let records = records.iter().map(|r| r.parse().unwrap()).collect();
+        Zone::new("example.com.", records).unwrap()
+    }
+
+    fn answers(packet: &DnsPacket) -> Vec<String> {
+        packet.answers.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn takes_its_timers_from_the_soa() {
+        let timers = zone().timers;
+        assert_eq!(timers.refresh, Duration::from_secs(3600));
+        assert_eq!(timers.retry, Duration::from_secs(600));
+        assert_eq!(timers.expire, Duration::from_secs(86400));
+    }
+
+    #[test]
+    fn finds_where_names_fall() {
+        let zone = zone();
+        assert!(matches!(
+            zone.position("www.example.com."),
+            Position::Node { .. }
+        ));
+        assert!(matches!(
+            zone.position("c.example.com."),
+            Position::EmptyNonTerminal
+        ));
+        assert!(matches!(
+            zone.position("b.c.example.com."),
+            Position::EmptyNonTerminal
+        ));
+        assert!(matches!(
+            zone.position("x.y.example.com."),
+            Position::Wildcard { .. }
+        ));
+        // the closest encloser is c.example.com., which has no wildcard
+        assert!(matches!(
+            zone.position("x.c.example.com."),
+            Position::NxDomain
+        ));
+        assert!(matches!(
+            zone.position("host.old.example.com."),
+            Position::Redirected { .. }
+        ));
+        assert!(matches!(
+            zone.position("old.example.com."),
+            Position::Node { .. }
+        ));
+        assert!(matches!(
+            zone.position("x.sub.example.com."),
+            Position::Delegated { .. }
+        ));
+    }
+
+    #[test]
+    fn answers_from_wildcards() {
+        let zone = zone();
+
+        let packet = zone.answer("x.y.example.com.", QueryType::A);
+        assert_eq!(packet.header.rescode, ResultCode::NOERROR);
+        assert_eq!(answers(&packet), ["x.y.example.com. 300 IN A 192.0.2.10"]);
+
+        let packet = zone.answer("x.y.example.com.", QueryType::AAAA);
+        assert_eq!(packet.header.rescode, ResultCode::NOERROR);
+        assert!(packet.answers.is_empty());
+        assert_eq!(packet.authorities[0].qtype(), QueryType::SOA);
+
+        let packet = zone.answer("x.c.example.com.", QueryType::A);
+        assert_eq!(packet.header.rescode, ResultCode::NXDOMAIN);
+        assert_eq!(packet.authorities[0].ttl(), 300);
+    }
+
+    #[test]
+    fn answers_no_data_for_empty_non_terminals() {
+        let packet = zone().answer("b.c.example.com.", QueryType::A);
+        assert_eq!(packet.header.rescode, ResultCode::NOERROR);
+        assert!(packet.answers.is_empty());
+        assert_eq!(packet.authorities[0].qtype(), QueryType::SOA);
+    }
+
+    #[test]
+    fn follows_dnames() {
+        let packet = zone().answer("host.old.example.com.", QueryType::A);
+        assert_eq!(packet.header.rescode, ResultCode::NOERROR);
+        assert_eq!(
+            answers(&packet),
+            [
+                "old.example.com. 300 IN DNAME new.example.com.",
+                "host.old.example.com. 300 IN CNAME host.new.example.com.",
+                "host.new.example.com. 300 IN A 192.0.2.3",
+            ]
+        );
+    }
+
+    #[test]
+    fn refers_below_zone_cuts() {
+        let packet = zone().answer("x.sub.example.com.", QueryType::A);
+        assert!(!packet.header.authoritative_answer);
+        assert!(packet.answers.is_empty());
+        assert_eq!(packet.authorities[0].qtype(), QueryType::NS);
+        assert_eq!(packet.resources[0].domain(), "ns.sub.example.com.");
+    }
+}
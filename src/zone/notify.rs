@@ -0,0 +1,95 @@
+//! Telling secondaries that a zone changed, so that they check its serial
+//! right away instead of at the next refresh (RFC 1996).
+//!
+//! Each secondary is sent a NOTIFY over UDP from a thread of its own, and
+//! sent it again until it answers or we give up.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{debug, warn};
+use rand::random;
+
+use crate::config::ZoneConfig;
+use crate::packet::{
+    buffer::BytePacketBuffer, name, opcode::Opcode, qtype::QueryType, question::DnsQuestion,
+    record::DnsRecord, rscode::ResultCode, DnsPacket,
+};
+
+/// How many times a secondary is sent a NOTIFY before giving up.
+const ATTEMPTS: usize = 5;
+
+/// Tell the secondaries of `zone` about its version with `soa`, waiting
+/// `timeout` for each to answer.
+pub fn send(zone: &ZoneConfig, soa: &DnsRecord, timeout: Duration) {
+    for target in &zone.notify {
+        let target = *target;
+        let soa = soa.clone();
+        thread::spawn(move || {
+            let origin = name::or_root(soa.domain()).to_string();
+            for attempt in 1..=ATTEMPTS {
+                match notify(target, &soa, timeout) {
+                    Ok(ResultCode::NOERROR) => {
+                        debug!("notified {} of zone {}", target, origin);
+                        return;
+                    }
+                    // asking again would get the same answer
+                    Ok(rescode) => {
+                        warn!(
+                            "{} refused NOTIFY of zone {}: {:?}",
+                            target, origin, rescode
+                        );
+                        return;
+                    }
+                    Err(e) => debug!(
+                        "cannot notify {} of zone {} (attempt {}): {:#}",
+                        target, origin, attempt, e
+                    ),
+                }
+            }
+            warn!(
+                "{} did not answer NOTIFY of zone {} after {} attempts",
+                target, origin, ATTEMPTS
+            );
+        });
+    }
+}
+
+/// Send a single NOTIFY for the zone with `soa` to `target` and wait for
+/// its answer.
+fn notify(target: SocketAddr, soa: &DnsRecord, timeout: Duration) -> Result<ResultCode> {
+    let mut packet = DnsPacket::new();
+    packet.header.id = random();
+    packet.header.opcode = Opcode::NOTIFY;
+    packet.header.authoritative_answer = true;
+    packet
+        .questions
+        .push(DnsQuestion::new(soa.domain().to_string(), QueryType::SOA));
+    // the serial lets the secondary skip the check if it has this version
+    packet.answers.push(soa.clone());
+
+    let mut req_buffer = BytePacketBuffer::new();
+    packet.write(&mut req_buffer)?;
+
+    let local = match target {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.send_to(&req_buffer.buf[0..req_buffer.pos()], target)?;
+
+    loop {
+        let (mut res_buffer, from) = BytePacketBuffer::recv_from(&socket)?;
+        let res = match DnsPacket::from_buffer(&mut res_buffer) {
+            Ok(res) => res,
+            Err(_) => continue,
+        };
+        if from != target || !res.header.response || res.header.id != packet.header.id {
+            continue;
+        }
+        return Ok(res.header.rescode);
+    }
+}
@@ -0,0 +1,82 @@
+//! Reloading the zones we are the primary for when their master file
+//! changes, keeping what changed in their journal and sending NOTIFY to
+//! their secondaries.
+//!
+//! Only the file named in the configuration is watched, not the files it
+//! includes.
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Result};
+use log::{debug, info, warn};
+
+use crate::config::{Config, ZoneConfig};
+use crate::packet::name;
+
+use super::{journal, notify, serial_gt, Catalog, Zone};
+
+/// How often master files are checked for changes.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Reload the zone of `zone` into `catalog` whenever its file changes,
+/// forever. Its secondaries are told about the version loaded at first
+/// too, in case it changed while we were down.
+pub fn watch(catalog: &Catalog, config: &Config, zone: &ZoneConfig) {
+    if let Some(loaded) = catalog.get(&zone.name) {
+        notify::send(zone, loaded.soa(), config.timeouts.upstream);
+    }
+    let mut loaded = modified(&zone.file);
+    loop {
+        thread::sleep(CHECK_INTERVAL);
+        let current = modified(&zone.file);
+        if current == loaded {
+            continue;
+        }
+        loaded = current;
+        if let Err(e) = reload(catalog, config, zone) {
+            warn!("cannot reload zone {}: {:#}", zone.name, e);
+        }
+    }
+}
+
+/// Load the zone of `zone` again, replacing the version served if it has a
+/// newer serial.
+pub fn reload(catalog: &Catalog, config: &Config, zone: &ZoneConfig) -> Result<()> {
+    let _changes = catalog.lock_changes();
+    let mut updated = Zone::load(&zone.name, &zone.file)?;
+    if let Some(current) = catalog.get(&zone.name) {
+        if !serial_gt(updated.serial(), current.serial()) {
+            if updated.records().eq(current.records()) {
+                debug!("zone {} is unchanged", zone.name);
+                return Ok(());
+            }
+            // secondaries would never notice the changes
+            bail!(
+                "serial {} is not newer than {}, keeping the old version",
+                updated.serial(),
+                current.serial()
+            );
+        }
+        updated.follow(&current);
+    }
+
+    let path = journal::path(&zone.file);
+    if let Err(e) = updated.journal.save(&path, &updated.origin) {
+        warn!("cannot save the journal of zone {}: {:#}", zone.name, e);
+    }
+    info!(
+        "reloaded zone {} with serial {}",
+        name::or_root(&updated.origin),
+        updated.serial()
+    );
+    notify::send(zone, updated.soa(), config.timeouts.upstream);
+    catalog.replace(updated);
+    Ok(())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,205 @@
+//! Keeping secondary zones in step with their primaries (RFC 1034, section
+//! 4.3.5).
+//!
+//! The serial of the primary's version is checked every SOA refresh
+//! interval, or every retry interval after a failure, and the zone is
+//! transferred when it is newer. A zone that could not be checked for the
+//! expire interval is no longer answered from. A NOTIFY from a primary
+//! has the serial checked right away (RFC 1996).
+
+use std::fs::{self, File};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, bail, Result};
+use log::{debug, info, warn};
+
+use crate::config::{Config, ZoneConfig};
+use crate::packet::{name, tsig};
+
+use super::transfer::{Primary, Transfer};
+use super::{journal, master, notify, serial_gt, Catalog, Zone};
+
+/// How long to wait before trying again when there is no SOA to tell yet.
+const INITIAL_RETRY: Duration = Duration::from_secs(60);
+
+/// Lets a NOTIFY cut short the wait until the next refresh.
+#[derive(Default)]
+pub struct Wakeup {
+    notified: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Wakeup {
+    pub fn wake(&self) {
+        *self.notified.lock().unwrap() = true;
+        self.condvar.notify_all();
+    }
+
+    /// Wait for `timeout` or until woken up, returning whether it was.
+    fn sleep(&self, timeout: Duration) -> bool {
+        let notified = self.notified.lock().unwrap();
+        let (mut notified, _) = self
+            .condvar
+            .wait_timeout_while(notified, timeout, |notified| !*notified)
+            .unwrap();
+        std::mem::replace(&mut *notified, false)
+    }
+}
+
+/// Keep the secondary zone of `zone` up to date in `catalog`, forever.
+pub fn maintain(catalog: &Catalog, config: &Config, zone: &ZoneConfig) {
+    // the saved copy is as fresh as the last time it was written
+    let mut refreshed = catalog.get(&zone.name).map(|_| saved_at(&zone.file));
+    let mut timers = catalog.get(&zone.name).map(|z| z.timers);
+    let wakeup = catalog.wakeup(&zone.name).unwrap_or_default();
+
+    loop {
+        let current = catalog.get(&zone.name);
+        let result = refresh(catalog, config, zone, current.as_deref());
+        if let Some(updated) = catalog.get(&zone.name) {
+            timers = Some(updated.timers);
+        }
+
+        let mut wait = match (&result, timers) {
+            (Ok(()), Some(timers)) => timers.refresh,
+            (Err(_), Some(timers)) => timers.retry,
+            (_, None) => INITIAL_RETRY,
+        };
+        match result {
+            Ok(()) => refreshed = Some(Instant::now()),
+            Err(e) => warn!("cannot refresh zone {}: {:#}", zone.name, e),
+        }
+
+        if let (Some(at), Some(timers), true) =
+            (refreshed, timers, catalog.get(&zone.name).is_some())
+        {
+            match timers.expire.checked_sub(at.elapsed()) {
+                Some(left) if !left.is_zero() => wait = wait.min(left),
+                _ => {
+                    warn!(
+                        "zone {} expired, not refreshed for {}s",
+                        zone.name,
+                        at.elapsed().as_secs()
+                    );
+                    catalog.expire(&zone.name);
+                }
+            }
+        }
+
+        debug!("checking zone {} again in {}s", zone.name, wait.as_secs());
+        if wakeup.sleep(wait) {
+            debug!("checking zone {} on NOTIFY", zone.name);
+        }
+    }
+}
+
+/// Check the serial at each primary in turn and transfer the zone from the
+/// first that answers, if its version is newer.
+fn refresh(
+    catalog: &Catalog,
+    config: &Config,
+    zone: &ZoneConfig,
+    current: Option<&Zone>,
+) -> Result<()> {
+    let key = zone
+        .primary_key
+        .as_deref()
+        .and_then(|key| config.tsig_key(key))
+        .map(|key| tsig::Key {
+            name: &key.name,
+            secret: &key.secret,
+        });
+
+    let mut errors = Vec::new();
+    for addr in &zone.primaries {
+        match refresh_from(*addr, key, catalog, config, zone, current) {
+            Ok(()) => return Ok(()),
+            Err(e) => errors.push(format!("{:#}", e)),
+        }
+    }
+    Err(anyhow!("{}", errors.join("; ")))
+}
+
+fn refresh_from(
+    addr: SocketAddr,
+    key: Option<tsig::Key>,
+    catalog: &Catalog,
+    config: &Config,
+    zone: &ZoneConfig,
+    current: Option<&Zone>,
+) -> Result<()> {
+    let mut primary = Primary::connect(addr, key, config.timeouts.upstream)?;
+    let serial = primary.serial(&zone.name)?;
+    if let Some(current) = current {
+        if !serial_gt(serial, current.serial()) {
+            if serial != current.serial() {
+                warn!(
+                    "primary {} has serial {} of zone {}, older than our {}",
+                    addr,
+                    serial,
+                    zone.name,
+                    current.serial()
+                );
+            }
+            debug!("zone {} is up to date at serial {}", zone.name, serial);
+            touch(&zone.file);
+            return Ok(());
+        }
+    }
+
+    let updated = match (primary.transfer(&zone.name, current)?, current) {
+        (Transfer::UpToDate, _) => {
+            touch(&zone.file);
+            return Ok(());
+        }
+        (Transfer::Full(records), Some(current)) => {
+            let mut updated = Zone::new(&zone.name, records)?;
+            updated.follow(current);
+            updated
+        }
+        (Transfer::Full(records), None) => Zone::new(&zone.name, records)?,
+        (Transfer::Incremental(diffs), Some(current)) => current.apply(&diffs)?,
+        (Transfer::Incremental(_), None) => bail!("changes sent for a zone we don't have"),
+    };
+    info!(
+        "transferred zone {} with serial {} from {}",
+        name::or_root(&updated.origin),
+        updated.serial(),
+        addr
+    );
+
+    // the journal lets us send the changes on to secondaries of our own
+    if let Err(e) = master::write(&zone.file, &updated) {
+        warn!("cannot save zone {}: {:#}", zone.name, e);
+    }
+    let path = journal::path(&zone.file);
+    if let Err(e) = updated.journal.save(&path, &updated.origin) {
+        warn!("cannot save the journal of zone {}: {:#}", zone.name, e);
+    }
+    notify::send(zone, updated.soa(), config.timeouts.upstream);
+    catalog.replace(updated);
+    Ok(())
+}
+
+/// When the saved copy of a zone at `path` was last written or found up to
+/// date.
+fn saved_at(path: &Path) -> Instant {
+    let age = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.elapsed().ok())
+        .unwrap_or_default();
+    Instant::now().checked_sub(age).unwrap_or_else(Instant::now)
+}
+
+/// Record that the saved copy at `path` is still up to date, so that it
+/// does not count as expired after a restart.
+fn touch(path: &Path) {
+    let _ = File::options()
+        .append(true)
+        .open(path)
+        .and_then(|file| file.set_modified(SystemTime::now()));
+}
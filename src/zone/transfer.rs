@@ -0,0 +1,284 @@
+//! The client side of zone transfers: asking a primary for the serial of a
+//! zone, then transferring it whole with AXFR (RFC 5936) or only what
+//! changed with IXFR (RFC 1995), over one TCP connection.
+//!
+//! With a key, every request is signed and every message of the answers
+//! must be signed too (RFC 8945, section 5.3.1).
+
+use std::collections::VecDeque;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::debug;
+use rand::random;
+
+use crate::packet::{
+    buffer::BytePacketBuffer,
+    name,
+    qtype::QueryType,
+    question::DnsQuestion,
+    record::DnsRecord,
+    rscode::ResultCode,
+    tsig::{self, Tsig},
+    DnsPacket,
+};
+
+use super::{serial_gt, soa_serial, Diff, Zone};
+
+/// What the primary sent for a zone.
+pub enum Transfer {
+    /// Nothing newer than the version we have.
+    UpToDate,
+    /// All the records of the zone.
+    Full(Vec<DnsRecord>),
+    /// The changes since the version we have.
+    Incremental(Vec<Diff>),
+}
+
+/// A connection to the primary server of a zone.
+pub struct Primary<'a> {
+    addr: SocketAddr,
+    stream: TcpStream,
+    key: Option<tsig::Key<'a>>,
+    /// The ID of the request being answered.
+    id: u16,
+    /// The MAC the next message's signature covers.
+    prior_mac: Option<Vec<u8>>,
+    /// How many messages of the answer were read so far.
+    messages: usize,
+    /// Records of the last message not yet looked at.
+    pending: VecDeque<DnsRecord>,
+}
+
+impl<'a> Primary<'a> {
+    pub fn connect(
+        addr: SocketAddr,
+        key: Option<tsig::Key<'a>>,
+        timeout: Duration,
+    ) -> Result<Primary<'a>> {
+        let stream = TcpStream::connect_timeout(&addr, timeout)
+            .with_context(|| format!("cannot connect to {}", addr))?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok(Primary {
+            addr,
+            stream,
+            key,
+            id: 0,
+            prior_mac: None,
+            messages: 0,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// The serial of the primary's version of the zone at `origin`.
+    pub fn serial(&mut self, origin: &str) -> Result<u32> {
+        self.send(origin, QueryType::SOA, None)?;
+        let res = self.receive()?;
+        if res.header.rescode != ResultCode::NOERROR || !res.header.authoritative_answer {
+            bail!(
+                "{} is not authoritative for {}: {:?}",
+                self.addr,
+                name::or_root(origin),
+                res.header.rescode
+            );
+        }
+        res.answers
+            .iter()
+            .filter(|rec| name::eq(rec.domain(), origin))
+            .find_map(soa_serial)
+            .ok_or_else(|| anyhow!("{} sent no SOA for {}", self.addr, name::or_root(origin)))
+    }
+
+    /// Transfer the zone at `origin`: only the changes since `current` if
+    /// there is a version of it and the primary can tell them, else all of
+    /// it.
+    pub fn transfer(&mut self, origin: &str, current: Option<&Zone>) -> Result<Transfer> {
+        if let Some(current) = current {
+            if let Some(transfer) = self.ixfr(origin, current)? {
+                return Ok(transfer);
+            }
+            debug!(
+                "{} sent no changes for {}, transferring all of it",
+                self.addr,
+                name::or_root(origin)
+            );
+        }
+        self.axfr(origin)
+    }
+
+    fn axfr(&mut self, origin: &str) -> Result<Transfer> {
+        self.send(origin, QueryType::AXFR, None)?;
+        let soa = self.next_record()?;
+        if soa.qtype() != QueryType::SOA {
+            bail!("transfer from {} does not start with the SOA", self.addr);
+        }
+        Ok(Transfer::Full(self.rest_of_zone(soa, Vec::new())?))
+    }
+
+    /// Ask for the changes since `current`, `None` if the primary cannot
+    /// tell them.
+    fn ixfr(&mut self, origin: &str, current: &Zone) -> Result<Option<Transfer>> {
+        self.send(origin, QueryType::IXFR, Some(current.soa()))?;
+        let first = self.receive()?;
+        if first.header.rescode != ResultCode::NOERROR || first.answers.is_empty() {
+            return Ok(None);
+        }
+        self.pending.extend(first.answers);
+
+        let soa = self.next_record()?;
+        let serial = match soa_serial(&soa) {
+            Some(serial) => serial,
+            None => bail!("transfer from {} does not start with the SOA", self.addr),
+        };
+        if !serial_gt(serial, current.serial()) {
+            return Ok(Some(Transfer::UpToDate));
+        }
+
+        // The changes start with the SOA of the version we have; anything
+        // else is the whole zone as AXFR would send it.
+        let next = self.next_record()?;
+        if next.qtype() != QueryType::SOA {
+            let records = self.rest_of_zone(soa, vec![next])?;
+            return Ok(Some(Transfer::Full(records)));
+        }
+        Ok(Some(Transfer::Incremental(self.changes(serial, next)?)))
+    }
+
+    /// Read the records of a whole zone up to the SOA that ends them.
+    fn rest_of_zone(
+        &mut self,
+        soa: DnsRecord,
+        mut records: Vec<DnsRecord>,
+    ) -> Result<Vec<DnsRecord>> {
+        loop {
+            let rec = self.next_record()?;
+            if rec.qtype() == QueryType::SOA {
+                break;
+            }
+            records.push(rec);
+        }
+        records.push(soa);
+        Ok(records)
+    }
+
+    /// Read the changes leading to `serial`, the first starting from the
+    /// SOA `old`, up to the SOA with `serial` that ends them.
+    fn changes(&mut self, serial: u32, mut old: DnsRecord) -> Result<Vec<Diff>> {
+        let mut diffs = Vec::new();
+        while soa_serial(&old) != Some(serial) {
+            let mut diff = Diff {
+                deleted: vec![old],
+                added: Vec::new(),
+            };
+            // the records deleted, up to the SOA of the next version
+            let new = loop {
+                let rec = self.next_record()?;
+                if rec.qtype() == QueryType::SOA {
+                    break rec;
+                }
+                diff.deleted.push(rec);
+            };
+            diff.added.push(new);
+            // the records added, up to the SOA the next changes start from
+            old = loop {
+                let rec = self.next_record()?;
+                if rec.qtype() == QueryType::SOA {
+                    break rec;
+                }
+                diff.added.push(rec);
+            };
+            diffs.push(diff);
+        }
+        Ok(diffs)
+    }
+
+    /// Send a request for the `qtype` records of `origin`, signed if we
+    /// have a key.
+    fn send(&mut self, origin: &str, qtype: QueryType, soa: Option<&DnsRecord>) -> Result<()> {
+        let mut packet = DnsPacket::new();
+        packet.header.id = random();
+        packet
+            .questions
+            .push(DnsQuestion::new(origin.to_string(), qtype));
+        packet.authorities.extend(soa.cloned());
+
+        let mut buffer = BytePacketBuffer::new();
+        packet.write(&mut buffer)?;
+        self.prior_mac = None;
+        if let Some(key) = &self.key {
+            let tsig = Tsig::sign(&buffer, key, None, false, ResultCode::NOERROR)?;
+            tsig.write(&mut buffer)?;
+            self.prior_mac = Some(tsig.mac);
+        }
+        buffer
+            .write_tcp(&mut self.stream)
+            .with_context(|| format!("cannot send to {}", self.addr))?;
+
+        self.id = packet.header.id;
+        self.messages = 0;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Read the next message of the answer, checking its signature.
+    fn receive(&mut self) -> Result<DnsPacket> {
+        let mut buffer = BytePacketBuffer::read_tcp(&mut self.stream)
+            .with_context(|| format!("cannot read from {}", self.addr))?
+            .ok_or_else(|| anyhow!("{} closed the connection", self.addr))?;
+        let packet = DnsPacket::from_buffer(&mut buffer)?;
+        if !packet.header.response || packet.header.id != self.id {
+            bail!("answer from {} does not match the request", self.addr);
+        }
+
+        if let Some(key) = &self.key {
+            let tsig = match &packet.tsig {
+                Some(tsig) if name::eq(&tsig.key_name, key.name) => tsig,
+                _ => bail!("answer from {} is not signed with {}", self.addr, key.name),
+            };
+            if tsig.error != ResultCode::NOERROR {
+                bail!(
+                    "{} does not accept our signature: TSIG error {}",
+                    self.addr,
+                    tsig.error.to_num()
+                );
+            }
+            // all but the first message sign the timers only
+            tsig.verify(
+                &buffer,
+                key.secret,
+                self.prior_mac.as_deref(),
+                self.messages > 0,
+            )
+            .map_err(|e| {
+                anyhow!(
+                    "bad signature from {}: TSIG error {}",
+                    self.addr,
+                    e.to_num()
+                )
+            })?;
+            self.prior_mac = Some(tsig.mac.clone());
+        }
+        self.messages += 1;
+        Ok(packet)
+    }
+
+    /// The next record of the answer, reading more messages as needed.
+    fn next_record(&mut self) -> Result<DnsRecord> {
+        loop {
+            if let Some(rec) = self.pending.pop_front() {
+                return Ok(rec);
+            }
+            let packet = self.receive()?;
+            if packet.header.rescode != ResultCode::NOERROR {
+                bail!(
+                    "{} refused the transfer: {:?}",
+                    self.addr,
+                    packet.header.rescode
+                );
+            }
+            self.pending.extend(packet.answers);
+        }
+    }
+}
@@ -0,0 +1,436 @@
+//! Changing the zones we are the primary for with UPDATE messages
+//! (RFC 2136).
+//!
+//! Once the prerequisites hold, all the changes of a message are made at
+//! once and the serial goes up by one, unless the message gave a newer SOA
+//! itself. The new version is saved to the zone's master file, which loses
+//! its comments and includes, and to its journal, and sent on to the
+//! secondaries.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use log::{debug, info, warn};
+
+use crate::config::{Config, ZoneConfig};
+use crate::packet::{
+    buffer::BytePacketBuffer, class::DnsClass, name, qtype::QueryType, record::DnsRecord,
+    rscode::ResultCode,
+};
+
+use super::{journal, key, master, notify, same_data, serial_gt, soa_serial, Catalog, Key, Zone};
+
+/// Check `prerequisites` against the zone of `zone` and make the changes of
+/// `updates` to it, telling the result to send back. An error means the
+/// zone could not be changed, with none of the changes made.
+pub fn update(
+    catalog: &Catalog,
+    config: &Config,
+    zone: &ZoneConfig,
+    prerequisites: &[DnsRecord],
+    updates: &[DnsRecord],
+) -> Result<ResultCode> {
+    let _changes = catalog.lock_changes();
+    let current = match catalog.get(&zone.name) {
+        Some(current) => current,
+        None => bail!("zone {} is not loaded", zone.name),
+    };
+    if let Err(rescode) = check_prerequisites(&current, prerequisites) {
+        return Ok(rescode);
+    }
+    if let Err(rescode) = prescan(&current, updates) {
+        return Ok(rescode);
+    }
+
+    let mut nodes = current.nodes.clone();
+    for rec in updates {
+        apply(&current.origin, &mut nodes, rec);
+    }
+    nodes.retain(|_, records| !records.is_empty());
+    if nodes == current.nodes {
+        debug!("update of zone {} changes nothing", zone.name);
+        return Ok(ResultCode::NOERROR);
+    }
+
+    let apex = nodes.entry(key(&current.origin)).or_default();
+    let soa = match apex.iter_mut().find(|r| r.qtype() == QueryType::SOA) {
+        Some(soa) => soa,
+        None => bail!("the SOA of zone {} is gone", zone.name),
+    };
+    if let DnsRecord::SOA { serial, .. } = soa {
+        if *serial == current.serial() {
+            *serial = serial.wrapping_add(1);
+        }
+    }
+    // the SOA first, as the master file has it
+    let soa = soa.clone();
+    let records = std::iter::once(soa.clone())
+        .chain(nodes.into_values().flatten().filter(|r| *r != soa))
+        .collect();
+
+    let mut updated = Zone::new(&current.origin, records)?;
+    updated.follow(&current);
+    master::write(&zone.file, &updated)?;
+    let path = journal::path(&zone.file);
+    if let Err(e) = updated.journal.save(&path, &updated.origin) {
+        warn!("cannot save the journal of zone {}: {:#}", zone.name, e);
+    }
+    info!(
+        "updated zone {} to serial {}",
+        name::or_root(&updated.origin),
+        updated.serial()
+    );
+    notify::send(zone, updated.soa(), config.timeouts.upstream);
+    catalog.replace(updated);
+    Ok(ResultCode::NOERROR)
+}
+
+/// Read a record of the prerequisite or update section of an UPDATE. These
+/// may leave out the rdata to stand for a whole RRset or all the records of
+/// a name (RFC 2136, section 2.4), which come back as records of unknown
+/// type with no data.
+pub fn read_record(buffer: &mut BytePacketBuffer) -> Result<DnsRecord> {
+    let start = buffer.pos();
+    let domain = buffer.read_qname()?;
+    let qtype = buffer.read_u16()?;
+    let class = DnsClass::from_num(buffer.read_u16()?);
+    let ttl = buffer.read_u32()?;
+    if buffer.read_u16()? == 0 {
+        return Ok(DnsRecord::UNKONWN {
+            domain,
+            class,
+            qtype,
+            data: Vec::new(),
+            ttl,
+        });
+    }
+    buffer.seek(start)?;
+    DnsRecord::read(buffer)
+}
+
+/// Whether a record of an UPDATE comes without rdata.
+fn is_empty(rec: &DnsRecord) -> bool {
+    matches!(rec, DnsRecord::UNKONWN { data, .. } if data.is_empty())
+}
+
+/// Types that only make sense in queries, or in no zone at all.
+fn is_meta(qtype: QueryType) -> bool {
+    matches!(
+        qtype,
+        QueryType::ANY | QueryType::AXFR | QueryType::IXFR | QueryType::OPT | QueryType::TSIG
+    )
+}
+
+/// Check the prerequisites of an UPDATE (RFC 2136, section 3.2), telling
+/// the first that fails.
+fn check_prerequisites(zone: &Zone, prerequisites: &[DnsRecord]) -> Result<(), ResultCode> {
+    // RRsets that must exist with exactly the records given
+    let mut rrsets: BTreeMap<(Key, u16), Vec<&DnsRecord>> = BTreeMap::new();
+
+    for rec in prerequisites {
+        if rec.ttl() != 0 {
+            return Err(ResultCode::FORMERR);
+        }
+        if !name::is_subdomain(rec.domain(), &zone.origin) {
+            return Err(ResultCode::NOTZONE);
+        }
+        let qtype = rec.qtype();
+        let records = zone
+            .nodes
+            .get(&key(rec.domain()))
+            .map_or(&[][..], Vec::as_slice);
+        let has_rrset = records.iter().any(|r| r.qtype() == qtype);
+
+        match (rec.class(), is_empty(rec)) {
+            (DnsClass::ANY, true) if qtype == QueryType::ANY => {
+                if records.is_empty() {
+                    return Err(ResultCode::NXDOMAIN);
+                }
+            }
+            (DnsClass::ANY, true) => {
+                if !has_rrset {
+                    return Err(ResultCode::NXRRSET);
+                }
+            }
+            (DnsClass::NONE, true) if qtype == QueryType::ANY => {
+                if !records.is_empty() {
+                    return Err(ResultCode::YXDOMAIN);
+                }
+            }
+            (DnsClass::NONE, true) => {
+                if has_rrset {
+                    return Err(ResultCode::YXRRSET);
+                }
+            }
+            (DnsClass::IN, false) if !is_meta(qtype) => rrsets
+                .entry((key(rec.domain()), qtype.to_num()))
+                .or_default()
+                .push(rec),
+            _ => return Err(ResultCode::FORMERR),
+        }
+    }
+
+    for ((owner, qtype), expected) in rrsets {
+        let actual: Vec<&DnsRecord> = zone
+            .nodes
+            .get(&owner)
+            .into_iter()
+            .flatten()
+            .filter(|r| r.qtype().to_num() == qtype)
+            .collect();
+        let same = actual
+            .iter()
+            .all(|a| expected.iter().any(|e| same_data(a, e)))
+            && expected
+                .iter()
+                .all(|e| actual.iter().any(|a| same_data(a, e)));
+        if !same {
+            return Err(ResultCode::NXRRSET);
+        }
+    }
+    Ok(())
+}
+
+/// Check that every change of an UPDATE can be made before making any
+/// (RFC 2136, section 3.4.1).
+fn prescan(zone: &Zone, updates: &[DnsRecord]) -> Result<(), ResultCode> {
+    for rec in updates {
+        if !name::is_subdomain(rec.domain(), &zone.origin) {
+            return Err(ResultCode::NOTZONE);
+        }
+        let qtype = rec.qtype();
+        match rec.class() {
+            DnsClass::IN if is_meta(qtype) || is_empty(rec) => return Err(ResultCode::FORMERR),
+            DnsClass::IN => {}
+            DnsClass::ANY if rec.ttl() == 0 && is_empty(rec) => {
+                if is_meta(qtype) && qtype != QueryType::ANY {
+                    return Err(ResultCode::FORMERR);
+                }
+            }
+            DnsClass::NONE if rec.ttl() == 0 && !is_meta(qtype) => {}
+            _ => return Err(ResultCode::FORMERR),
+        }
+    }
+    Ok(())
+}
+
+/// Make one change of an UPDATE to the records of the zone at `origin`
+/// (RFC 2136, section 3.4.2). Changes that would break the zone, such as
+/// deleting its SOA or a CNAME next to other data, are ignored.
+fn apply(origin: &str, nodes: &mut BTreeMap<Key, Vec<DnsRecord>>, rec: &DnsRecord) {
+    let at_apex = name::eq(rec.domain(), origin);
+    let qtype = rec.qtype();
+    let node = nodes.entry(key(rec.domain())).or_default();
+
+    match rec.class() {
+        DnsClass::IN => {
+            match qtype {
+                // only a newer SOA replaces the zone's
+                QueryType::SOA => {
+                    let soa = node.iter_mut().find(|r| r.qtype() == QueryType::SOA);
+                    if let (true, Some(soa)) = (at_apex, soa) {
+                        if serial_gt(soa_serial(rec).unwrap(), soa_serial(soa).unwrap()) {
+                            *soa = rec.clone();
+                        }
+                    }
+                    return;
+                }
+                QueryType::CNAME => {
+                    if node.iter().any(|r| r.qtype() != QueryType::CNAME) {
+                        return;
+                    }
+                    node.clear();
+                }
+                _ => {
+                    if node.iter().any(|r| r.qtype() == QueryType::CNAME) {
+                        return;
+                    }
+                }
+            }
+            // the same record again only changes its TTL
+            node.retain(|r| !same_data(r, rec));
+            node.push(rec.clone());
+        }
+        DnsClass::ANY => node.retain(|r| {
+            let keeps_zone = at_apex && matches!(r.qtype(), QueryType::SOA | QueryType::NS);
+            keeps_zone || (qtype != QueryType::ANY && r.qtype() != qtype)
+        }),
+        DnsClass::NONE => {
+            let mut target = rec.clone();
+            target.set_class(DnsClass::IN);
+            let nameservers = node.iter().filter(|r| r.qtype() == QueryType::NS).count();
+            let last_nameserver = at_apex && qtype == QueryType::NS && nameservers <= 1;
+            if qtype != QueryType::SOA && !last_nameserver {
+                node.retain(|r| !same_data(r, &target));
+            }
+        }
+        _ => unreachable!("prescan lets only these classes through"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn a(domain: &str, class: DnsClass, ttl: u32, last: u8) -> DnsRecord {
+        DnsRecord::A {
+            domain: domain.to_string(),
+            class,
+            addr: Ipv4Addr::new(192, 0, 2, last),
+            ttl,
+        }
+    }
+
+    fn empty(domain: &str, class: DnsClass, qtype: QueryType) -> DnsRecord {
+        DnsRecord::UNKONWN {
+            domain: domain.to_string(),
+            class,
+            qtype: qtype.to_num(),
+            data: Vec::new(),
+            ttl: 0,
+        }
+    }
+
+    fn zone() -> Zone {
+        let records = vec![
+            DnsRecord::SOA {
+                domain: "example.com.".to_string(),
+                class: DnsClass::IN,
+                ttl: 3600,
+                mname: "ns.example.com.".to_string(),
+                rname: "hostmaster.example.com.".to_string(),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            },
+            DnsRecord::NS {
+                domain: "example.com.".to_string(),
+                class: DnsClass::IN,
+                host: "ns.example.com.".to_string(),
+                ttl: 3600,
+            },
+            a("ns.example.com.", DnsClass::IN, 300, 53),
+            a("www.example.com.", DnsClass::IN, 300, 1),
+            a("www.example.com.", DnsClass::IN, 300, 2),
+        ];
+        Zone::new("example.com.", records).unwrap()
+    }
+
+    fn updated(zone: &Zone, updates: &[DnsRecord]) -> BTreeMap<Key, Vec<DnsRecord>> {
+        assert_eq!(prescan(zone, updates), Ok(()));
+        let mut nodes = zone.nodes.clone();
+        for rec in updates {
+            apply(&zone.origin, &mut nodes, rec);
+        }
+        nodes.retain(|_, records| !records.is_empty());
+        nodes
+    }
+
+    #[test]
+    fn reads_records_without_rdata() {
+        let mut buffer = BytePacketBuffer::new();
+        buffer.write_qname("www.example.com.").unwrap();
+        buffer.write_u16(QueryType::A.to_num()).unwrap();
+        buffer.write_u16(DnsClass::ANY.to_num()).unwrap();
+        buffer.write_u32(0).unwrap();
+        buffer.write_u16(0).unwrap();
+        a("www.example.com.", DnsClass::IN, 300, 1)
+            .write(&mut buffer)
+            .unwrap();
+
+        buffer.seek(0).unwrap();
+        let rec = read_record(&mut buffer).unwrap();
+        assert_eq!(rec, empty("www.example.com.", DnsClass::ANY, QueryType::A));
+        let rec = read_record(&mut buffer).unwrap();
+        assert_eq!(rec, a("www.example.com.", DnsClass::IN, 300, 1));
+    }
+
+    #[test]
+    fn checks_prerequisites() {
+        let zone = zone();
+        let www = "www.example.com.";
+        let check = |prerequisites: &[DnsRecord]| check_prerequisites(&zone, prerequisites);
+
+        // name is in use, or not
+        assert_eq!(check(&[empty(www, DnsClass::ANY, QueryType::ANY)]), Ok(()));
+        assert_eq!(
+            check(&[empty("mail.example.com.", DnsClass::ANY, QueryType::ANY)]),
+            Err(ResultCode::NXDOMAIN)
+        );
+        assert_eq!(
+            check(&[empty(www, DnsClass::NONE, QueryType::ANY)]),
+            Err(ResultCode::YXDOMAIN)
+        );
+        // RRset exists, or not
+        assert_eq!(check(&[empty(www, DnsClass::ANY, QueryType::A)]), Ok(()));
+        assert_eq!(
+            check(&[empty(www, DnsClass::ANY, QueryType::AAAA)]),
+            Err(ResultCode::NXRRSET)
+        );
+        assert_eq!(
+            check(&[empty(www, DnsClass::NONE, QueryType::A)]),
+            Err(ResultCode::YXRRSET)
+        );
+        // RRset exists with exactly these records
+        let both = [a(www, DnsClass::IN, 0, 2), a(www, DnsClass::IN, 0, 1)];
+        assert_eq!(check(&both), Ok(()));
+        assert_eq!(check(&both[..1]), Err(ResultCode::NXRRSET));
+        // malformed or outside the zone
+        assert_eq!(
+            check(&[a(www, DnsClass::IN, 300, 1)]),
+            Err(ResultCode::FORMERR)
+        );
+        assert_eq!(
+            check(&[empty("example.org.", DnsClass::ANY, QueryType::ANY)]),
+            Err(ResultCode::NOTZONE)
+        );
+    }
+
+    #[test]
+    fn deletes_rrsets_names_and_records() {
+        let zone = zone();
+        let www = "www.example.com.";
+
+        let nodes = updated(&zone, &[empty(www, DnsClass::ANY, QueryType::A)]);
+        assert!(!nodes.contains_key(&key(www)));
+
+        let nodes = updated(&zone, &[empty(www, DnsClass::ANY, QueryType::AAAA)]);
+        assert_eq!(nodes, zone.nodes);
+
+        let nodes = updated(&zone, &[a(www, DnsClass::NONE, 0, 1)]);
+        assert_eq!(nodes[&key(www)], [a(www, DnsClass::IN, 300, 2)]);
+
+        let nodes = updated(&zone, &[empty(www, DnsClass::ANY, QueryType::ANY)]);
+        assert!(!nodes.contains_key(&key(www)));
+
+        // the SOA and NS of the apex stay
+        let nodes = updated(
+            &zone,
+            &[empty("example.com.", DnsClass::ANY, QueryType::ANY)],
+        );
+        assert_eq!(nodes, zone.nodes);
+    }
+
+    #[test]
+    fn prescan_rejects_malformed_updates() {
+        let zone = zone();
+        let www = "www.example.com.";
+
+        assert_eq!(
+            prescan(&zone, &[empty(www, DnsClass::IN, QueryType::A)]),
+            Err(ResultCode::FORMERR)
+        );
+        assert_eq!(
+            prescan(&zone, &[a(www, DnsClass::ANY, 300, 1)]),
+            Err(ResultCode::FORMERR)
+        );
+        assert_eq!(
+            prescan(&zone, &[a("www.example.org.", DnsClass::IN, 300, 1)]),
+            Err(ResultCode::NOTZONE)
+        );
+    }
+}